@@ -0,0 +1,3 @@
+-- Remove allocation history
+DROP INDEX scan_allocation_instrument;
+DROP TABLE scan_allocation;
@@ -0,0 +1,11 @@
+-- Record of every scan number allocated and who requested it
+CREATE TABLE scan_allocation (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    instrument TEXT NOT NULL,
+    scan_number INTEGER NOT NULL,
+    -- Subject of the token used to request the scan - null if auth is disabled
+    subject TEXT,
+    -- RFC3339 timestamp of when the number was allocated
+    allocated_at TEXT NOT NULL
+);
+CREATE INDEX scan_allocation_instrument ON scan_allocation (instrument);
@@ -0,0 +1,3 @@
+-- Always use tracker files if a directory is present
+ALTER TABLE instrument
+DROP COLUMN use_tracker_file;
@@ -0,0 +1,3 @@
+-- Allow instruments to opt out of file based scan number tracking
+ALTER TABLE instrument
+ADD COLUMN use_tracker_file BOOLEAN NOT NULL DEFAULT TRUE;
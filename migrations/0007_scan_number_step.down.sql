@@ -0,0 +1,3 @@
+-- Always increment scan numbers by 1
+ALTER TABLE instrument
+DROP COLUMN scan_number_step;
@@ -0,0 +1,3 @@
+-- Allow scan numbers to be allocated with a stride other than 1
+ALTER TABLE instrument
+ADD COLUMN scan_number_step INTEGER NOT NULL DEFAULT 1 CHECK (scan_number_step > 0);
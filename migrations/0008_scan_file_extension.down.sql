@@ -0,0 +1,3 @@
+-- Scan file paths are always returned without an extension
+ALTER TABLE instrument
+DROP COLUMN scan_file_extension;
@@ -0,0 +1,3 @@
+-- Optional extension appended to the scan file path for an instrument
+ALTER TABLE instrument
+ADD COLUMN scan_file_extension TEXT;
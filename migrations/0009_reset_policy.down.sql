@@ -0,0 +1,5 @@
+-- Scan numbers are never reset
+ALTER TABLE instrument
+DROP COLUMN last_allocated_year;
+ALTER TABLE instrument
+DROP COLUMN reset_policy;
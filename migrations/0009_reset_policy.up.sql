@@ -0,0 +1,6 @@
+-- Allow the scan number of an instrument to be reset at the start of each year
+ALTER TABLE instrument
+ADD COLUMN reset_policy TEXT NOT NULL DEFAULT 'NEVER' CHECK (reset_policy IN ('NEVER', 'YEARLY'));
+-- The year the last scan number was allocated in, used to detect the start of a new year
+ALTER TABLE instrument
+ADD COLUMN last_allocated_year INTEGER;
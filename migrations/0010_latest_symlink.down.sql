@@ -0,0 +1,3 @@
+-- No symlinks are maintained for any instrument
+ALTER TABLE instrument
+DROP COLUMN latest_symlink;
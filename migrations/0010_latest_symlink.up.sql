@@ -0,0 +1,3 @@
+-- Allow instruments to opt in to a 'latest' symlink pointing at the most recent scan
+ALTER TABLE instrument
+ADD COLUMN latest_symlink BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+-- Instrument sessions are not validated for any instrument
+ALTER TABLE instrument
+DROP COLUMN visit_pattern;
@@ -0,0 +1,3 @@
+-- Allow instruments to restrict the format of the instrument sessions used to build paths
+ALTER TABLE instrument
+ADD COLUMN visit_pattern TEXT;
@@ -0,0 +1,5 @@
+-- Every instrument uses tracker directories
+ALTER TABLE instrument
+DROP COLUMN tracker_url;
+ALTER TABLE instrument
+DROP COLUMN tracker_mode;
@@ -0,0 +1,7 @@
+-- Allow the scan number of an instrument to be tracked by an external HTTP service instead of
+-- a directory of number files
+ALTER TABLE instrument
+ADD COLUMN tracker_mode TEXT NOT NULL DEFAULT 'FILE' CHECK (tracker_mode IN ('FILE', 'HTTP'));
+-- The URL of the tracker service for instruments using HTTP tracking
+ALTER TABLE instrument
+ADD COLUMN tracker_url TEXT;
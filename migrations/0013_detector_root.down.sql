@@ -0,0 +1,3 @@
+-- Detector paths are always relative to the data directory
+ALTER TABLE instrument
+DROP COLUMN detector_root;
@@ -0,0 +1,4 @@
+-- Allow detector data to be written somewhere other than the data directory, eg a fast local
+-- disk. The root is an absolute directory template.
+ALTER TABLE instrument
+ADD COLUMN detector_root TEXT;
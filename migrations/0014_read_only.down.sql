@@ -0,0 +1,3 @@
+-- Every instrument can allocate scan numbers
+ALTER TABLE instrument
+DROP COLUMN read_only;
@@ -0,0 +1,3 @@
+-- Allow an instrument's scan numbering to be frozen, eg during maintenance
+ALTER TABLE instrument
+ADD COLUMN read_only BOOLEAN NOT NULL DEFAULT FALSE;
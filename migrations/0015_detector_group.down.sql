@@ -0,0 +1,3 @@
+-- Remove detector groups
+DROP INDEX detector_group_instrument;
+DROP TABLE detector_group;
@@ -0,0 +1,9 @@
+-- Named groups of detectors that are expanded to their members when requested for a scan. Each
+-- member is a separate row and members are kept in the order they were added.
+CREATE TABLE detector_group (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    instrument TEXT NOT NULL,
+    name TEXT NOT NULL CHECK (length(name) > 0),
+    detector TEXT NOT NULL CHECK (length(detector) > 0)
+);
+CREATE INDEX detector_group_instrument ON detector_group (instrument);
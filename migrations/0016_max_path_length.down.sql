@@ -0,0 +1,3 @@
+-- Paths can be any length for every instrument
+ALTER TABLE instrument
+DROP COLUMN max_path_length;
@@ -0,0 +1,4 @@
+-- Allow instruments to limit the length of the paths generated for each scan, eg for storage
+-- systems that reject long paths
+ALTER TABLE instrument
+ADD COLUMN max_path_length INTEGER;
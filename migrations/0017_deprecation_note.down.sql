@@ -0,0 +1,3 @@
+-- No instrument templates can be marked as deprecated
+ALTER TABLE instrument
+DROP COLUMN deprecation_note;
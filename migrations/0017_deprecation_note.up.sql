@@ -0,0 +1,4 @@
+-- Allow staff to flag an instrument's templates as deprecated so that clients can be warned
+-- before the templates are changed
+ALTER TABLE instrument
+ADD COLUMN deprecation_note TEXT;
@@ -0,0 +1,3 @@
+-- Configuration changes are no longer counted
+ALTER TABLE instrument
+DROP COLUMN version;
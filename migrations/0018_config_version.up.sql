@@ -0,0 +1,3 @@
+-- Count changes to each instrument's configuration so that concurrent edits can be detected
+ALTER TABLE instrument
+ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,5 @@
+-- Every instrument uses filesystem paths
+ALTER TABLE instrument
+DROP COLUMN base_uri;
+ALTER TABLE instrument
+DROP COLUMN path_scheme;
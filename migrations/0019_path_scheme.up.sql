@@ -0,0 +1,7 @@
+-- Allow the paths of an instrument to be returned as URIs for object stores instead of
+-- filesystem paths
+ALTER TABLE instrument
+ADD COLUMN path_scheme TEXT NOT NULL DEFAULT 'FILE' CHECK (path_scheme IN ('FILE', 'URI'));
+-- The URI that paths are joined onto for instruments using the URI scheme
+ALTER TABLE instrument
+ADD COLUMN base_uri TEXT;
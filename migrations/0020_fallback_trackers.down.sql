@@ -0,0 +1,3 @@
+-- Each instrument only uses its main tracker
+ALTER TABLE instrument
+DROP COLUMN fallback_trackers;
@@ -0,0 +1,4 @@
+-- Additional trackers that are kept up to date alongside an instrument's main tracker, stored as
+-- a comma separated list of tracker modes, eg 'HTTP'
+ALTER TABLE instrument
+ADD COLUMN fallback_trackers TEXT;
@@ -0,0 +1,3 @@
+-- Every tracker file is used regardless of its number
+ALTER TABLE instrument
+DROP COLUMN max_acceptable_file_number;
@@ -0,0 +1,4 @@
+-- Tracker files numbered above this are ignored so that a stray file can't take over the
+-- scan numbering
+ALTER TABLE instrument
+ADD COLUMN max_acceptable_file_number INTEGER;
@@ -0,0 +1,62 @@
+// Copyright 2024 Diamond Light Source
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures::StreamExt as _;
+use tracing::debug;
+
+use crate::cli::AuditOptions;
+use crate::db_service::SqliteScanPathService;
+
+/// Check every template stored in the DB against the current validation rules, eg to find
+/// templates that were accepted before a rule was tightened. The DB is opened read-only and
+/// nothing is modified.
+///
+/// Each invalid template is printed to stdout. Returns true if every template is valid.
+pub async fn audit_templates(opts: AuditOptions) -> bool {
+    debug!(?opts, "Auditing stored templates");
+    let db = match SqliteScanPathService::open_read_only(&opts.db).await {
+        Ok(db) => db,
+        Err(e) => {
+            println!("Could not open DB {:?}: {e}", opts.db);
+            return false;
+        }
+    };
+    let mut configurations = db.all_configurations_stream();
+    let mut instruments = 0;
+    let mut invalid = 0;
+    while let Some(conf) = configurations.next().await {
+        let conf = match conf {
+            Ok(conf) => conf,
+            Err(e) => {
+                println!("[FAIL] Could not read configuration: {e}");
+                invalid += 1;
+                continue;
+            }
+        };
+        instruments += 1;
+        for (kind, template, err) in conf.invalid_templates() {
+            println!(
+                "[FAIL] {}: {kind} template {template:?}: {err}",
+                conf.name()
+            );
+            invalid += 1;
+        }
+    }
+    if invalid == 0 {
+        println!("All templates for {instruments} instruments are valid");
+    } else {
+        println!("{invalid} invalid templates found");
+    }
+    invalid == 0
+}
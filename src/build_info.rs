@@ -1,6 +1,7 @@
 //! Compile time build information provided by built
 
 use chrono::Local;
+use opentelemetry::global;
 use serde::Serialize;
 
 include!(concat!(env!("OUT_DIR"), "/built.rs"));
@@ -29,14 +30,26 @@ pub struct ServerStatus {
     version: String,
     start_time: String,
     build: String,
+    /// The version of the latest DB migration applied when the server started
+    migration_version: Option<i64>,
 }
 
 impl ServerStatus {
-    pub fn new() -> Self {
+    /// Record the status of a running server. The migration version is also exported as the
+    /// `db.migration.version` gauge.
+    pub fn new(migration_version: Option<i64>) -> Self {
+        if let Some(version) = migration_version {
+            global::meter("numtracker")
+                .i64_gauge("db.migration.version")
+                .with_description("The version of the latest DB migration applied")
+                .build()
+                .record(version, &[]);
+        }
         Self {
             version: PKG_VERSION.into(),
             start_time: Local::now().to_rfc3339(),
             build: GIT_COMMIT_HASH.unwrap_or("Unknown").into(),
+            migration_version,
         }
     }
 }
@@ -0,0 +1,72 @@
+// Copyright 2024 Diamond Light Source
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use tracing::debug;
+
+use crate::cli::CheckOptions;
+use crate::db_service::SqliteScanPathService;
+use crate::numtracker::NumTracker;
+
+/// Check that the DB and tracker directories can be read without modifying either of them.
+///
+/// A summary of each check is printed to stdout. Returns true if all checks passed.
+pub async fn run_checks(opts: CheckOptions) -> bool {
+    debug!(?opts, "Checking numtracker environment");
+    let db = check_db(&opts).await;
+    let root = check_root_directory(&opts);
+    let passed = db && root;
+    println!("{}", if passed { "PASS" } else { "FAIL" });
+    passed
+}
+
+async fn check_db(opts: &CheckOptions) -> bool {
+    match SqliteScanPathService::integrity_check(&opts.db).await {
+        Ok(problems) if problems.is_empty() => {
+            println!(
+                "[ OK ] DB {:?} is readable and passed integrity check",
+                opts.db
+            );
+            true
+        }
+        Ok(problems) => {
+            println!("[FAIL] DB {:?} failed integrity check:", opts.db);
+            for problem in problems {
+                println!("           {problem}");
+            }
+            false
+        }
+        Err(e) => {
+            println!("[FAIL] DB {:?} could not be read: {e}", opts.db);
+            false
+        }
+    }
+}
+
+fn check_root_directory(opts: &CheckOptions) -> bool {
+    if opts.root_directory.is_empty() {
+        println!("[SKIP] No root directory configured");
+        return true;
+    }
+    let mut passed = true;
+    for root in &opts.root_directory {
+        match NumTracker::for_root_directory(Some(root)) {
+            Ok(_) => println!("[ OK ] Root directory {root:?} is readable"),
+            Err(e) => {
+                println!("[FAIL] Root directory {root:?} could not be read: {e}");
+                passed = false;
+            }
+        }
+    }
+    passed
+}
@@ -26,10 +26,24 @@ pub struct ConnectionOptions {
     /// eg https://authn.example.com/realms/master
     #[clap(long, env = "NUMTRACKER_AUTH_HOST")]
     pub auth: Option<Url>,
+    /// The client ID to use when authenticating with the authorisation provider
+    ///
+    /// Defaults to 'numtracker' if not given here or in the configuration file
+    #[clap(long, env = "NUMTRACKER_AUTH_CLIENT_ID")]
+    pub client_id: Option<String>,
 }
 
 #[derive(Debug, Subcommand)]
 pub enum ClientCommand {
+    #[clap(flatten)]
+    Service(ServiceCommand),
+    /// Remove any cached credentials so that the next request requires logging in again
+    Logout,
+}
+
+/// Commands that make requests to the numtracker service
+#[derive(Debug, Subcommand)]
+pub enum ServiceCommand {
     /// Query existing configurations
     Configuration {
         #[clap(short)]
@@ -38,6 +52,9 @@ pub enum ClientCommand {
     /// Update or add new configurations
     Configure {
         beamline: String,
+        /// Only show the fields that were changed by the update
+        #[clap(long)]
+        show_diff: bool,
         #[clap(flatten)]
         config: ConfigurationOptions,
     },
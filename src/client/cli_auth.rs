@@ -1,3 +1,4 @@
+use std::io::ErrorKind;
 use std::path::PathBuf;
 
 use derive_more::{Display, Error, From};
@@ -13,8 +14,8 @@ use openidconnect::{
     DeviceCodeErrorResponseType, DiscoveryError, EmptyAdditionalClaims, EmptyExtraTokenFields,
     EndpointMaybeSet, EndpointNotSet, EndpointSet, HttpClientError, IdTokenFields, IssuerUrl,
     OAuth2TokenResponse, ProviderMetadata, RefreshToken, RequestTokenError,
-    RevocationErrorResponseType, StandardErrorResponse, StandardTokenIntrospectionResponse,
-    StandardTokenResponse,
+    RevocationErrorResponseType, RevocationUrl, StandardErrorResponse,
+    StandardTokenIntrospectionResponse, StandardTokenResponse,
 };
 use reqwest::redirect::Policy;
 use serde::{Deserialize, Serialize};
@@ -26,6 +27,9 @@ use url::Url;
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct DeviceEndpointProviderMetadata {
     device_authorization_endpoint: DeviceAuthorizationUrl,
+    /// RFC 7009 endpoint used to revoke tokens - not all providers support this
+    #[serde(default)]
+    revocation_endpoint: Option<RevocationUrl>,
 }
 
 impl AdditionalProviderMetadata for DeviceEndpointProviderMetadata {}
@@ -83,20 +87,40 @@ type HttpError = HttpClientError<reqwest::Error>;
 pub struct AuthHandler {
     http: reqwest::Client,
     auth: DeviceFlowClient,
+    revocation_url: Option<RevocationUrl>,
 }
 
 #[derive(Debug, Display, Error, From)]
 pub enum AuthError {
     Http(reqwest::Error),
     Discovery(DiscoveryError<HttpError>),
+    #[display("Failed to start device flow (check the client ID is correct): {_0}")]
     DeviceFlowInit(RequestTokenError<HttpError, StandardErrorResponse<CoreErrorResponseType>>),
     AccessRequest(RequestTokenError<HttpError, StandardErrorResponse<DeviceCodeErrorResponseType>>),
+    #[from(ignore)]
+    #[display("Failed to refresh access token: {_0}")]
+    Refresh(RequestTokenError<HttpError, StandardErrorResponse<CoreErrorResponseType>>),
     Oidc(openidconnect::ConfigurationError),
     NoVerificationUrl,
+    Revocation(RequestTokenError<HttpError, StandardErrorResponse<RevocationErrorResponseType>>),
+    #[display("Auth server does not support token revocation")]
+    RevocationUnsupported,
+}
+
+impl AuthError {
+    /// Whether the auth server rejected a refresh token as invalid, eg because it has expired or
+    /// been revoked, rather than the refresh failing for another reason
+    fn is_rejected_refresh(&self) -> bool {
+        matches!(
+            self,
+            AuthError::Refresh(RequestTokenError::ServerResponse(resp))
+                if *resp.error() == CoreErrorResponseType::InvalidGrant
+        )
+    }
 }
 
 impl AuthHandler {
-    pub async fn new(host: impl Into<Url>) -> Result<Self, AuthError> {
+    pub async fn new(host: impl Into<Url>, client_id: &str) -> Result<Self, AuthError> {
         let http_client = reqwest::ClientBuilder::new()
             .redirect(Policy::none())
             .build()?;
@@ -107,9 +131,13 @@ impl AuthHandler {
             .additional_metadata()
             .device_authorization_endpoint
             .clone();
+        let revocation_url = meta_provider
+            .additional_metadata()
+            .revocation_endpoint
+            .clone();
         let client = CoreClient::from_provider_metadata(
             meta_provider,
-            ClientId::new("numtracker".to_string()),
+            ClientId::new(client_id.to_string()),
             None,
         )
         .set_device_authorization_url(device_authorization_url)
@@ -117,6 +145,7 @@ impl AuthHandler {
         Ok(Self {
             http: http_client,
             auth: client,
+            revocation_url,
         })
     }
 
@@ -144,13 +173,27 @@ impl AuthHandler {
         Ok(token)
     }
 
-    pub async fn refresh_flow(&self, token: String) -> Option<impl OAuth2TokenResponse> {
+    pub async fn refresh_flow(&self, token: String) -> Result<impl OAuth2TokenResponse, AuthError> {
         self.auth
-            .exchange_refresh_token(&RefreshToken::new(token))
-            .ok()?
+            .exchange_refresh_token(&RefreshToken::new(token))?
             .request_async(&self.http)
             .await
-            .ok()
+            .map_err(AuthError::Refresh)
+    }
+
+    /// Revoke a refresh token so that it can no longer be used to get new access tokens
+    pub async fn revoke(&self, token: String) -> Result<(), AuthError> {
+        let url = self
+            .revocation_url
+            .clone()
+            .ok_or(AuthError::RevocationUnsupported)?;
+        self.auth
+            .clone()
+            .set_revocation_url(url)
+            .revoke_token(RefreshToken::new(token).into())?
+            .request_async(&self.http)
+            .await?;
+        Ok(())
     }
 }
 
@@ -184,17 +227,44 @@ async fn save_refresh_token(token: &str) {
     }
 }
 
+/// Remove the saved refresh token if there is one
+async fn clear_refresh_token() -> Result<(), std::io::Error> {
+    trace!("Clearing refresh token");
+    let Some(file) = token_file().await else {
+        return Ok(());
+    };
+    match fs::remove_file(&file).await {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
 async fn retrieve_refresh_token() -> Option<String> {
     trace!("Retrieving refresh token");
     fs::read_to_string(&token_file().await?).await.ok()
 }
 
 /// Retrieve a saved refresh token if there is one and use it to request a new access token
-/// If a new access token is acquired, replace the saved refresh token as well
+/// If a new access token is acquired, replace the saved refresh token as well. If the auth server
+/// rejects the refresh token, the saved token is removed so that it is not retried on subsequent
+/// runs. Other failures, eg the server being unavailable, leave it in place.
 async fn refresh_access_token(auth: &AuthHandler) -> Option<String> {
     debug!("Trying to get access token via refresh");
     let refresh = retrieve_refresh_token().await?;
-    let tokens = auth.refresh_flow(refresh).await?;
+    let tokens = match auth.refresh_flow(refresh).await {
+        Ok(tokens) => tokens,
+        Err(e) if e.is_rejected_refresh() => {
+            debug!("Refresh token was rejected: {e}");
+            if let Err(e) = clear_refresh_token().await {
+                warn!("Failed to remove invalid refresh token: {e}");
+            }
+            return None;
+        }
+        Err(e) => {
+            warn!("{e}");
+            return None;
+        }
+    };
     if let Some(refr) = tokens.refresh_token() {
         save_refresh_token(refr.secret()).await;
     }
@@ -203,9 +273,9 @@ async fn refresh_access_token(auth: &AuthHandler) -> Option<String> {
 
 /// Get a new access token from the auth server via the device flow.
 /// If successful, cache the refresh token to prevent needing to log in next time
-pub(crate) async fn get_access_token(h: &Url) -> Result<String, AuthError> {
+pub(crate) async fn get_access_token(h: &Url, client_id: &str) -> Result<String, AuthError> {
     debug!("Getting new access token");
-    let handler = AuthHandler::new(h.clone()).await?;
+    let handler = AuthHandler::new(h.clone(), client_id).await?;
     if let Some(token) = refresh_access_token(&handler).await {
         return Ok(token);
     }
@@ -215,3 +285,32 @@ pub(crate) async fn get_access_token(h: &Url) -> Result<String, AuthError> {
     }
     Ok(token.access_token().clone().into_secret())
 }
+
+/// Try to revoke a refresh token on the auth server. Failures are reported but are not fatal as
+/// the token is removed locally regardless.
+async fn revoke_refresh_token(host: &Url, client_id: &str, token: String) {
+    let handler = match AuthHandler::new(host.clone(), client_id).await {
+        Ok(handler) => handler,
+        Err(e) => {
+            println!("Warning: could not connect to auth server to revoke token: {e}");
+            return;
+        }
+    };
+    match handler.revoke(token).await {
+        Ok(()) => debug!("Refresh token revoked"),
+        Err(AuthError::RevocationUnsupported) => {
+            debug!("Auth server does not support revocation - only removing local token")
+        }
+        Err(e) => println!("Warning: failed to revoke refresh token: {e}"),
+    }
+}
+
+/// Remove any cached credentials so that the next request requires logging in again. If the auth
+/// server and client ID are given, the refresh token is also revoked on the server.
+pub(crate) async fn logout(auth: Option<(&Url, &str)>) -> Result<(), std::io::Error> {
+    debug!("Logging out");
+    if let (Some((host, client_id)), Some(token)) = (auth, retrieve_refresh_token().await) {
+        revoke_refresh_token(host, client_id, token).await;
+    }
+    clear_refresh_token().await
+}
@@ -12,6 +12,7 @@ use url::Url;
 pub struct ClientConfiguration {
     pub host: Option<Url>,
     pub auth: Option<Url>,
+    pub client_id: Option<String>,
 }
 
 #[derive(Debug, Display, Error, From)]
@@ -60,6 +61,11 @@ impl ClientConfiguration {
         self.auth = auth.or(self.auth);
         self
     }
+
+    pub(crate) fn with_client_id(mut self, client_id: Option<String>) -> Self {
+        self.client_id = client_id.or(self.client_id);
+        self
+    }
 }
 
 impl Display for ClientConfiguration {
@@ -74,6 +80,11 @@ impl Display for ClientConfiguration {
             Some(ref a) => write!(f, "{a}")?,
             None => write!(f, "None")?,
         }
+        write!(f, ", client_id: ")?;
+        match self.client_id {
+            Some(ref c) => write!(f, "{c}")?,
+            None => write!(f, "None")?,
+        }
         write!(f, ")")?;
         Ok(())
     }
@@ -8,18 +8,24 @@ use serde::Serialize;
 use tracing::info;
 use url::Url;
 
-use crate::cli::client::{ClientCommand, ClientOptions, ConfigurationOptions};
+use crate::cli::client::{ClientCommand, ClientOptions, ConfigurationOptions, ServiceCommand};
 
 mod cli_auth;
 mod config;
 
+/// The client ID used to authenticate if none is configured
+const DEFAULT_CLIENT_ID: &str = "numtracker";
+
 #[derive(Debug, Display, Error, From)]
 pub enum ClientError {
     Auth(AuthError),
     Network(reqwest::Error),
+    #[display("Could not read current configuration")]
+    CurrentConfiguration,
 }
 
-pub async fn run_client(options: ClientOptions) {
+/// Run a client command, returning whether it completed successfully
+pub async fn run_client(options: ClientOptions) -> bool {
     let ClientOptions {
         connection,
         command,
@@ -28,37 +34,61 @@ pub async fn run_client(options: ClientOptions) {
     let conf = match ClientConfiguration::from_default_file().await {
         Ok(conf) => {
             info!("Configuration from file: {conf}");
-            conf.with_host(connection.host).with_auth(connection.auth)
+            conf.with_host(connection.host)
+                .with_auth(connection.auth)
+                .with_client_id(connection.client_id)
         }
         Err(e) => {
             println!("Could not read configuration: {e}");
-            return;
+            return false;
         }
     };
 
     info!("Configuration with CLI args included: {conf}");
 
+    let command = match command {
+        ClientCommand::Service(command) => command,
+        ClientCommand::Logout => {
+            let client_id = conf.client_id.as_deref().unwrap_or(DEFAULT_CLIENT_ID);
+            let auth = conf.auth.as_ref().map(|auth| (auth, client_id));
+            return match cli_auth::logout(auth).await {
+                Ok(()) => {
+                    println!("Removed cached credentials");
+                    true
+                }
+                Err(e) => {
+                    println!("Could not remove cached credentials: {e}");
+                    false
+                }
+            };
+        }
+    };
+
     let client = match NumtrackerClient::from_config(conf).await {
         Ok(client) => client,
         Err(e) => {
             println!("Error initialising client: {e}");
-            return;
+            return false;
         }
     };
 
     let result = match command {
-        ClientCommand::Configuration { beamline } => client.query_configuration(beamline).await,
-        ClientCommand::Configure { beamline, config } => {
-            client.configure_beamline(beamline, config).await
-        }
-        ClientCommand::VisitDirectory { beamline, visit } => {
+        ServiceCommand::Configuration { beamline } => client.query_configuration(beamline).await,
+        ServiceCommand::Configure {
+            beamline,
+            show_diff,
+            config,
+        } => client.configure_beamline(beamline, config, show_diff).await,
+        ServiceCommand::VisitDirectory { beamline, visit } => {
             client.query_visit_directory(beamline, visit).await
         }
     };
 
     if let Err(e) = result {
         println!("Error querying service: {e}");
+        return false;
     }
+    true
 }
 
 struct NumtrackerClient {
@@ -98,7 +128,13 @@ impl NumtrackerClient {
         });
 
         let auth = match config.auth {
-            Some(auth) => Some(cli_auth::get_access_token(&auth).await?),
+            Some(auth) => {
+                let client_id = config.client_id.as_deref().unwrap_or_else(|| {
+                    info!("No client ID specified, defaulting to {DEFAULT_CLIENT_ID:?}");
+                    DEFAULT_CLIENT_ID
+                });
+                Some(cli_auth::get_access_token(&auth, client_id).await?)
+            }
             None => None,
         };
         info!("Querying {host} with auth: {auth:?}");
@@ -138,8 +174,14 @@ impl NumtrackerClient {
             for conf in configs.configurations {
                 println!("Beamline: {}", conf.instrument);
                 println!("    Visit Template: {}", conf.directory_template);
-                println!("    Scan Template: {}", conf.scan_template);
-                println!("    Detector Template: {}", conf.detector_template);
+                println!(
+                    "    Scan Template: {}",
+                    conf.scan_template.as_deref().unwrap_or("Not Available")
+                );
+                println!(
+                    "    Detector Template: {}",
+                    conf.detector_template.as_deref().unwrap_or("Not Available")
+                );
                 println!("    DB Scan Number: {}", conf.db_scan_number);
                 match conf.file_scan_number {
                     Some(file_num) => println!("    File Scan Number: {file_num}"),
@@ -180,7 +222,34 @@ impl NumtrackerClient {
         self,
         instrument: String,
         config: ConfigurationOptions,
+        show_diff: bool,
     ) -> Result<(), ClientError> {
+        // Fetch the existing configuration first so that the changes can be shown
+        let before = if show_diff {
+            let vars = configuration_query::Variables {
+                instrument: Some(vec![instrument.clone()]),
+            };
+            let request = ConfigurationQuery::build_query(vars);
+            let data = self
+                .request::<_, configuration_query::ResponseData>(request)
+                .await?;
+            if data.errors.is_some() {
+                print_errors(data.errors.as_deref());
+                return Err(ClientError::CurrentConfiguration);
+            }
+            data.data
+                .and_then(|data| data.configurations.into_iter().next())
+                .map(|conf| ConfigurationFields {
+                    directory: conf.directory_template,
+                    scan: conf.scan_template,
+                    detector: conf.detector_template,
+                    db_scan_number: conf.db_scan_number,
+                    file_scan_number: conf.file_scan_number,
+                    tracker_file_extension: conf.tracker_file_extension,
+                })
+        } else {
+            None
+        };
         let vars = configure_mutation::Variables {
             instrument,
             scan: config.scan,
@@ -198,18 +267,19 @@ impl NumtrackerClient {
         match data.data {
             Some(data) => {
                 let conf = data.configure;
-                println!("Visit Template: {}", conf.directory_template);
-                println!("Scan Template: {}", conf.scan_template);
-                println!("Detector Template: {}", conf.detector_template);
-                println!("DB Scan Number: {}", conf.db_scan_number);
-                match conf.file_scan_number {
-                    Some(file_num) => println!("File Scan Number: {file_num}"),
-                    None => println!("File Scan Number: Not Available"),
+                let after = ConfigurationFields {
+                    directory: conf.directory_template,
+                    scan: conf.scan_template,
+                    detector: conf.detector_template,
+                    db_scan_number: conf.db_scan_number,
+                    file_scan_number: conf.file_scan_number,
+                    tracker_file_extension: conf.tracker_file_extension,
+                };
+                if show_diff {
+                    after.print_diff(before.as_ref());
+                } else {
+                    after.print();
                 }
-                println!(
-                    "Tracker File Extension: {}",
-                    conf.tracker_file_extension.as_deref().unwrap_or("None")
-                );
             }
             None => println!("No configuration returned from server"),
         }
@@ -217,6 +287,83 @@ impl NumtrackerClient {
     }
 }
 
+/// The fields of an instrument's configuration that are shown after it is updated
+#[derive(Debug)]
+struct ConfigurationFields {
+    directory: String,
+    scan: Option<String>,
+    detector: Option<String>,
+    db_scan_number: i64,
+    file_scan_number: Option<i64>,
+    tracker_file_extension: Option<String>,
+}
+
+impl ConfigurationFields {
+    /// The label and displayed value of each field
+    fn fields(&self) -> [(&'static str, String); 6] {
+        [
+            ("Visit Template", self.directory.clone()),
+            (
+                "Scan Template",
+                self.scan.as_deref().unwrap_or("Not Available").into(),
+            ),
+            (
+                "Detector Template",
+                self.detector.as_deref().unwrap_or("Not Available").into(),
+            ),
+            ("DB Scan Number", self.db_scan_number.to_string()),
+            (
+                "File Scan Number",
+                self.file_scan_number
+                    .map_or_else(|| "Not Available".into(), |num| num.to_string()),
+            ),
+            (
+                "Tracker File Extension",
+                self.tracker_file_extension
+                    .as_deref()
+                    .unwrap_or("None")
+                    .into(),
+            ),
+        ]
+    }
+
+    fn print(&self) {
+        for (label, value) in self.fields() {
+            println!("{label}: {value}");
+        }
+    }
+
+    /// Print only the fields that differ from a previous configuration. If there was no previous
+    /// configuration, every field is new and the full configuration is printed.
+    fn print_diff(&self, before: Option<&Self>) {
+        for line in self.diff(before) {
+            println!("{line}");
+        }
+    }
+
+    /// The lines printed by [`print_diff`](Self::print_diff)
+    fn diff(&self, before: Option<&Self>) -> Vec<String> {
+        let Some(before) = before else {
+            let fields = self.fields().into_iter();
+            return std::iter::once("New configuration".into())
+                .chain(fields.map(|(label, value)| format!("{label}: {value}")))
+                .collect();
+        };
+        let changes = before
+            .fields()
+            .into_iter()
+            .zip(self.fields())
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((label, old), (_, new))| format!("{label}: {old} -> {new}"))
+            .collect::<Vec<_>>();
+        if changes.is_empty() {
+            vec!["No changes".into()]
+        } else {
+            changes
+        }
+    }
+}
+
 fn print_errors(errors: Option<&[graphql_client::Error]>) {
     if let Some(errors) = errors {
         println!("Query returned errors:");
@@ -225,3 +372,58 @@ fn print_errors(errors: Option<&[graphql_client::Error]>) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ConfigurationFields;
+
+    fn fields() -> ConfigurationFields {
+        ConfigurationFields {
+            directory: "/data/{instrument}/{year}/{visit}".into(),
+            scan: Some("{subdirectory}/{instrument}-{scan_number}".into()),
+            detector: None,
+            db_scan_number: 122,
+            file_scan_number: Some(122),
+            tracker_file_extension: None,
+        }
+    }
+
+    #[test]
+    fn diff_new_configuration() {
+        assert_eq!(
+            fields().diff(None),
+            [
+                "New configuration",
+                "Visit Template: /data/{instrument}/{year}/{visit}",
+                "Scan Template: {subdirectory}/{instrument}-{scan_number}",
+                "Detector Template: Not Available",
+                "DB Scan Number: 122",
+                "File Scan Number: 122",
+                "Tracker File Extension: None",
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_unchanged() {
+        assert_eq!(fields().diff(Some(&fields())), ["No changes"]);
+    }
+
+    #[test]
+    fn diff_changed_fields() {
+        let after = ConfigurationFields {
+            scan: Some("{instrument}-{scan_number}".into()),
+            detector: Some("{scan_number}-{detector}".into()),
+            db_scan_number: 200,
+            ..fields()
+        };
+        assert_eq!(
+            after.diff(Some(&fields())),
+            [
+                "Scan Template: {subdirectory}/{instrument}-{scan_number} -> {instrument}-{scan_number}",
+                "Detector Template: Not Available -> {scan_number}-{detector}",
+                "DB Scan Number: 122 -> 200",
+            ]
+        );
+    }
+}
@@ -15,12 +15,15 @@
 use std::fmt;
 use std::marker::PhantomData;
 use std::path::Path;
+use std::time::Duration;
 
-pub use error::ConfigurationError;
-use error::NewConfigurationError;
-use sqlx::sqlite::{SqliteConnectOptions, SqliteRow};
-use sqlx::{query_as, FromRow, QueryBuilder, Row, Sqlite, SqlitePool};
-use tracing::{info, instrument, trace};
+use chrono::Utc;
+pub use error::{ConfigurationError, NewConfigurationError};
+use futures::future::ready;
+use futures::{Stream, TryStreamExt as _};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePoolOptions, SqliteRow};
+use sqlx::{query, query_as, FromRow, QueryBuilder, Row, Sqlite, SqlitePool, Transaction};
+use tracing::{info, instrument, trace, warn};
 
 use crate::paths::{
     DetectorField, DetectorTemplate, DirectoryField, DirectoryTemplate, InvalidPathTemplate,
@@ -30,12 +33,60 @@ use crate::template::PathTemplate;
 
 type SqliteTemplateResult<F> = Result<PathTemplate<F>, InvalidPathTemplate>;
 
+/// Limits on the number of connections kept open to the DB
+///
+/// SQLite only allows a single writer at a time so additional connections only help concurrent
+/// reads. Writes will still be serialised (and may wait on the busy timeout) regardless of the
+/// size of the pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolSize {
+    /// The number of connections to keep open even when idle
+    pub min: u32,
+    /// The maximum number of connections to open at once
+    pub max: u32,
+}
+
+impl Default for PoolSize {
+    fn default() -> Self {
+        // Match the sqlx defaults
+        Self { min: 0, max: 10 }
+    }
+}
+
+/// How connecting to the DB should be retried if it fails when the server starts, eg while the
+/// volume containing it is being mounted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectRetry {
+    /// The number of times to retry after the first attempt fails
+    pub retries: u32,
+    /// The delay before the first retry. This is doubled for each following retry, up to
+    /// [ConnectRetry::MAX_INTERVAL].
+    pub interval: Duration,
+}
+
+impl ConnectRetry {
+    /// The longest delay between two attempts, however many retries there are
+    pub const MAX_INTERVAL: Duration = Duration::from_secs(30);
+
+    /// The delay before the given retry, counting from 0
+    fn delay(&self, retry: u32) -> Duration {
+        self.interval
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(Self::MAX_INTERVAL)
+    }
+}
+
 #[derive(Clone)]
 pub struct SqliteScanPathService {
     pool: SqlitePool,
+    /// Optional read-only replica used for queries. All writes go to the primary pool.
+    replica: Option<SqlitePool>,
+    /// The number of scan allocations to keep for each instrument. Older allocations are removed
+    /// as new ones are recorded. All allocations are kept if this is `None`.
+    allocation_retention: Option<u32>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct RawPathTemplate<F>(String, PhantomData<F>);
 
 impl<Spec> RawPathTemplate<Spec>
@@ -60,7 +111,7 @@ impl<F> From<&str> for RawPathTemplate<F> {
 }
 
 /// The current configuration for an instrument
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct InstrumentConfiguration {
     name: String,
     scan_number: u32,
@@ -68,6 +119,23 @@ pub struct InstrumentConfiguration {
     scan: RawPathTemplate<ScanTemplate>,
     detector: RawPathTemplate<DetectorTemplate>,
     tracker_file_extension: Option<String>,
+    use_tracker_file: bool,
+    scan_number_step: u32,
+    scan_file_extension: Option<String>,
+    reset_policy: ResetPolicy,
+    latest_symlink: bool,
+    visit_pattern: Option<String>,
+    tracker_mode: TrackerMode,
+    tracker_url: Option<String>,
+    detector_root: Option<RawPathTemplate<DirectoryTemplate>>,
+    read_only: bool,
+    max_path_length: Option<u32>,
+    deprecation_note: Option<String>,
+    version: i64,
+    path_scheme: PathScheme,
+    base_uri: Option<String>,
+    fallback_trackers: Vec<TrackerMode>,
+    max_acceptable_file_number: Option<u32>,
 }
 
 impl InstrumentConfiguration {
@@ -91,14 +159,249 @@ impl InstrumentConfiguration {
         self.detector.as_template()
     }
 
+    /// The directory template exactly as it is stored
+    pub fn raw_directory(&self) -> &str {
+        &self.directory.0
+    }
+
+    /// The scan template exactly as it is stored
+    pub fn raw_scan(&self) -> &str {
+        &self.scan.0
+    }
+
+    /// The detector template exactly as it is stored
+    pub fn raw_detector(&self) -> &str {
+        &self.detector.0
+    }
+
     pub fn tracker_file_extension(&self) -> Option<&str> {
         self.tracker_file_extension.as_deref()
     }
+
+    /// Whether the tracker directory for this instrument (if there is one) should be used
+    pub fn use_tracker_file(&self) -> bool {
+        self.use_tracker_file
+    }
+
+    /// The amount the scan number is increased by for each new scan
+    pub fn scan_number_step(&self) -> u32 {
+        self.scan_number_step
+    }
+
+    /// The extension appended to scan file paths, if any
+    pub fn scan_file_extension(&self) -> Option<&str> {
+        self.scan_file_extension.as_deref()
+    }
+
+    /// When the scan number for this instrument is reset
+    pub fn reset_policy(&self) -> ResetPolicy {
+        self.reset_policy
+    }
+
+    /// Whether a 'latest' symlink to the most recent scan should be kept in the data directory
+    pub fn latest_symlink(&self) -> bool {
+        self.latest_symlink
+    }
+
+    /// The pattern that instrument sessions must match to be used with this instrument, if any
+    pub fn visit_pattern(&self) -> Option<&str> {
+        self.visit_pattern.as_deref()
+    }
+
+    /// Where the external scan number for this instrument is tracked
+    pub fn tracker_mode(&self) -> TrackerMode {
+        self.tracker_mode
+    }
+
+    /// The URL of the service tracking the scan number when using [TrackerMode::Http]
+    pub fn tracker_url(&self) -> Option<&str> {
+        self.tracker_url.as_deref()
+    }
+
+    /// The root directory detector paths are relative to, if they are not written to the data
+    /// directory
+    pub fn detector_root(&self) -> Option<SqliteTemplateResult<DirectoryField>> {
+        self.detector_root
+            .as_ref()
+            .map(RawPathTemplate::as_template)
+    }
+
+    /// Whether the instrument is frozen so that no scan numbers can be allocated and its
+    /// configuration can't be changed
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// The maximum length in bytes of the absolute paths generated for a scan, if there is one
+    pub fn max_path_length(&self) -> Option<u32> {
+        self.max_path_length
+    }
+
+    /// A note explaining why this instrument's templates are deprecated, if they are
+    pub fn deprecation_note(&self) -> Option<&str> {
+        self.deprecation_note.as_deref()
+    }
+
+    /// The number of times this configuration has been changed, starting from 1 when the
+    /// instrument is added
+    pub fn version(&self) -> i64 {
+        self.version
+    }
+
+    /// How the paths generated for this instrument are returned
+    pub fn path_scheme(&self) -> PathScheme {
+        self.path_scheme
+    }
+
+    /// The URI that paths are joined onto when using [PathScheme::Uri]
+    pub fn base_uri(&self) -> Option<&str> {
+        self.base_uri.as_deref()
+    }
+
+    /// Trackers that are kept up to date alongside the main tracker, in the order they are used
+    pub fn fallback_trackers(&self) -> &[TrackerMode] {
+        &self.fallback_trackers
+    }
+
+    /// The highest tracker file number that is used when finding the latest scan number, if
+    /// there is a limit. Files with higher numbers are ignored.
+    pub fn max_acceptable_file_number(&self) -> Option<u32> {
+        self.max_acceptable_file_number
+    }
+
+    /// Check every stored template against the current rules for its kind, returning the name,
+    /// stored value and error of each template that would now be rejected
+    pub fn invalid_templates(&self) -> Vec<(&'static str, &str, InvalidPathTemplate)> {
+        fn check<'t, S: PathSpec>(
+            name: &'static str,
+            raw: &'t RawPathTemplate<S>,
+        ) -> Option<(&'static str, &'t str, InvalidPathTemplate)> {
+            raw.as_template().err().map(|e| (name, raw.0.as_str(), e))
+        }
+        [
+            check("directory", &self.directory),
+            check("scan", &self.scan),
+            check("detector", &self.detector),
+            self.detector_root
+                .as_ref()
+                .and_then(|root| check("detectorRoot", root)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// When the scan number of an instrument should be reset
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ResetPolicy {
+    /// Scan numbers keep increasing indefinitely
+    #[default]
+    Never,
+    /// The first scan allocated in a new calendar year is numbered 1
+    Yearly,
+}
+
+impl ResetPolicy {
+    /// The value stored in the DB for this policy
+    fn as_db(self) -> &'static str {
+        match self {
+            ResetPolicy::Never => "NEVER",
+            ResetPolicy::Yearly => "YEARLY",
+        }
+    }
+
+    /// Convert a value stored in the DB. The DB only allows valid values so anything else is
+    /// treated as the default.
+    fn from_db(value: &str) -> Self {
+        match value {
+            "YEARLY" => ResetPolicy::Yearly,
+            _ => ResetPolicy::Never,
+        }
+    }
+}
+
+/// Where the external scan number of an instrument is tracked
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TrackerMode {
+    /// Number files in the instrument's tracker directory, as used by GDA
+    #[default]
+    File,
+    /// An external HTTP service at the instrument's tracker URL
+    Http,
+}
+
+impl TrackerMode {
+    /// The value stored in the DB for this mode
+    fn as_db(self) -> &'static str {
+        match self {
+            TrackerMode::File => "FILE",
+            TrackerMode::Http => "HTTP",
+        }
+    }
+
+    /// Convert a value stored in the DB. The DB only allows valid values so anything else is
+    /// treated as the default.
+    fn from_db(value: &str) -> Self {
+        match value {
+            "HTTP" => TrackerMode::Http,
+            _ => TrackerMode::File,
+        }
+    }
+
+    /// The value stored in the DB for a list of modes. An empty list is stored as NULL.
+    fn list_as_db(modes: &[Self]) -> Option<String> {
+        (!modes.is_empty()).then(|| {
+            modes
+                .iter()
+                .map(|mode| mode.as_db())
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+    }
+
+    /// Convert a list of modes stored in the DB
+    fn list_from_db(value: Option<&str>) -> Vec<Self> {
+        value
+            .into_iter()
+            .flat_map(|modes| modes.split(','))
+            .map(Self::from_db)
+            .collect()
+    }
+}
+
+/// How the paths generated for an instrument are returned
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PathScheme {
+    /// Paths on a POSIX filesystem
+    #[default]
+    File,
+    /// URIs joined onto the instrument's base URI, eg for an object store
+    Uri,
+}
+
+impl PathScheme {
+    /// The value stored in the DB for this scheme
+    fn as_db(self) -> &'static str {
+        match self {
+            PathScheme::File => "FILE",
+            PathScheme::Uri => "URI",
+        }
+    }
+
+    /// Convert a value stored in the DB. The DB only allows valid values so anything else is
+    /// treated as the default.
+    fn from_db(value: &str) -> Self {
+        match value {
+            "URI" => PathScheme::Uri,
+            _ => PathScheme::File,
+        }
+    }
 }
 
 impl<'r> FromRow<'r, SqliteRow> for InstrumentConfiguration {
     fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
-        Ok(DbInstrumentConfig {
+        DbInstrumentConfig {
             id: None,
             name: row.try_get("name")?,
             scan_number: row.try_get("scan_number")?,
@@ -106,8 +409,70 @@ impl<'r> FromRow<'r, SqliteRow> for InstrumentConfiguration {
             scan: row.try_get::<String, _>("scan")?,
             detector: row.try_get::<String, _>("detector")?,
             tracker_file_extension: row.try_get::<Option<String>, _>("tracker_file_extension")?,
+            use_tracker_file: row.try_get("use_tracker_file")?,
+            scan_number_step: row.try_get("scan_number_step")?,
+            scan_file_extension: row.try_get::<Option<String>, _>("scan_file_extension")?,
+            reset_policy: row.try_get("reset_policy")?,
+            last_allocated_year: row.try_get("last_allocated_year")?,
+            latest_symlink: row.try_get("latest_symlink")?,
+            visit_pattern: row.try_get::<Option<String>, _>("visit_pattern")?,
+            tracker_mode: row.try_get("tracker_mode")?,
+            tracker_url: row.try_get::<Option<String>, _>("tracker_url")?,
+            detector_root: row.try_get::<Option<String>, _>("detector_root")?,
+            read_only: row.try_get("read_only")?,
+            max_path_length: row.try_get::<Option<i64>, _>("max_path_length")?,
+            deprecation_note: row.try_get::<Option<String>, _>("deprecation_note")?,
+            version: row.try_get("version")?,
+            path_scheme: row.try_get("path_scheme")?,
+            base_uri: row.try_get::<Option<String>, _>("base_uri")?,
+            fallback_trackers: row.try_get::<Option<String>, _>("fallback_trackers")?,
+            max_acceptable_file_number: row
+                .try_get::<Option<i64>, _>("max_acceptable_file_number")?,
         }
-        .into())
+        .try_into()
+        .map_err(|e| sqlx::Error::Decode(Box::new(e)))
+    }
+}
+
+/// A record of a scan number being allocated
+#[derive(Debug, PartialEq, Eq)]
+pub struct ScanAllocation {
+    scan_number: u32,
+    subject: Option<String>,
+    allocated_at: String,
+}
+
+impl ScanAllocation {
+    pub fn scan_number(&self) -> u32 {
+        self.scan_number
+    }
+
+    /// The subject of the token used to request the scan, if authorization was enabled
+    pub fn subject(&self) -> Option<&str> {
+        self.subject.as_deref()
+    }
+
+    /// RFC3339 timestamp of when the scan number was allocated
+    pub fn allocated_at(&self) -> &str {
+        &self.allocated_at
+    }
+}
+
+/// A named set of detectors that is expanded to its members when requested for a scan
+#[derive(Debug, PartialEq, Eq)]
+pub struct DetectorGroup {
+    name: String,
+    detectors: Vec<String>,
+}
+
+impl DetectorGroup {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The members of the group in the order they were added
+    pub fn detectors(&self) -> &[String] {
+        &self.detectors
     }
 }
 
@@ -119,6 +484,30 @@ pub struct InstrumentConfigurationUpdate {
     pub scan: Option<PathTemplate<ScanField>>,
     pub detector: Option<PathTemplate<DetectorField>>,
     pub tracker_file_extension: Option<String>,
+    pub use_tracker_file: Option<bool>,
+    pub scan_number_step: Option<u32>,
+    pub scan_file_extension: Option<String>,
+    pub reset_policy: Option<ResetPolicy>,
+    pub latest_symlink: Option<bool>,
+    /// An empty pattern removes any existing pattern
+    pub visit_pattern: Option<String>,
+    pub tracker_mode: Option<TrackerMode>,
+    pub tracker_url: Option<String>,
+    /// `Some(None)` removes any existing detector root
+    pub detector_root: Option<Option<PathTemplate<DirectoryField>>>,
+    /// A length of 0 removes any existing limit
+    pub max_path_length: Option<u32>,
+    /// An empty note removes any existing note
+    pub deprecation_note: Option<String>,
+    pub path_scheme: Option<PathScheme>,
+    pub base_uri: Option<String>,
+    /// An empty list removes any existing fallback trackers
+    pub fallback_trackers: Option<Vec<TrackerMode>>,
+    /// A number of 0 removes any existing limit
+    pub max_acceptable_file_number: Option<u32>,
+    /// Reject the update if the configuration is not currently at this version. An instrument
+    /// that does not exist is treated as being at version 0.
+    pub expected_version: Option<i64>,
 }
 
 impl InstrumentConfigurationUpdate {
@@ -128,21 +517,80 @@ impl InstrumentConfigurationUpdate {
             && self.scan.is_none()
             && self.detector.is_none()
             && self.tracker_file_extension.is_none()
+            && self.use_tracker_file.is_none()
+            && self.scan_number_step.is_none()
+            && self.scan_file_extension.is_none()
+            && self.reset_policy.is_none()
+            && self.latest_symlink.is_none()
+            && self.visit_pattern.is_none()
+            && self.tracker_mode.is_none()
+            && self.tracker_url.is_none()
+            && self.detector_root.is_none()
+            && self.max_path_length.is_none()
+            && self.deprecation_note.is_none()
+            && self.path_scheme.is_none()
+            && self.base_uri.is_none()
+            && self.fallback_trackers.is_none()
+            && self.max_acceptable_file_number.is_none()
+    }
+
+    /// Apply this update to an instrument's configuration, adding a new instrument if it is not
+    /// already present. Both steps are made in a single transaction so a failure leaves any
+    /// existing configuration unchanged.
+    pub async fn apply(
+        self,
+        db: &SqliteScanPathService,
+    ) -> Result<InstrumentConfiguration, NewConfigurationError> {
+        let mut tx = db.pool.begin().await?;
+        if let Some(expected) = self.expected_version {
+            let current = sqlx::query_scalar("SELECT version FROM instrument WHERE name = ?")
+                .bind(&self.name)
+                .fetch_optional(&mut *tx)
+                .await?
+                .unwrap_or(0);
+            if current != expected {
+                return Err(NewConfigurationError::Conflict {
+                    instrument: self.name,
+                    expected,
+                    current,
+                });
+            }
+        }
+        let config = match self.update_with(&mut tx).await? {
+            Some(config) => config,
+            None => self.insert_with(&mut tx).await?,
+        };
+        tx.commit().await?;
+        Ok(config)
     }
 
+    #[cfg(test)]
     pub async fn update_instrument(
         &self,
         db: &SqliteScanPathService,
+    ) -> Result<Option<InstrumentConfiguration>, sqlx::Error> {
+        self.update_with(&mut *db.pool.acquire().await?).await
+    }
+
+    async fn update_with(
+        &self,
+        conn: &mut SqliteConnection,
     ) -> Result<Option<InstrumentConfiguration>, sqlx::Error> {
         if self.is_empty() {
-            return match db.current_configuration(&self.name).await {
-                Ok(bc) => Ok(Some(bc)),
-                Err(ConfigurationError::MissingInstrument(_)) => Ok(None),
-                Err(ConfigurationError::Db(e)) => Err(e),
-            };
+            return query_as!(
+                DbInstrumentConfig,
+                "SELECT * FROM instrument WHERE name = ?",
+                self.name
+            )
+            .fetch_optional(&mut *conn)
+            .await?
+            .map(InstrumentConfiguration::try_from)
+            .transpose()
+            .map_err(|e| sqlx::Error::Decode(Box::new(e)));
         }
         let mut q: QueryBuilder<Sqlite> = QueryBuilder::new("UPDATE instrument SET ");
         let mut fields = q.separated(", ");
+        fields.push("version=version + 1");
         if let Some(num) = self.scan_number {
             fields.push("scan_number=");
             fields.push_bind_unseparated(num);
@@ -166,6 +614,66 @@ impl InstrumentConfigurationUpdate {
                 fields.push_bind_unseparated(ext);
             }
         }
+        if let Some(use_file) = self.use_tracker_file {
+            fields.push("use_tracker_file=");
+            fields.push_bind_unseparated(use_file);
+        }
+        if let Some(step) = self.scan_number_step {
+            fields.push("scan_number_step=");
+            fields.push_bind_unseparated(step);
+        }
+        if let Some(ext) = &self.scan_file_extension {
+            fields.push("scan_file_extension=");
+            fields.push_bind_unseparated(ext);
+        }
+        if let Some(policy) = self.reset_policy {
+            fields.push("reset_policy=");
+            fields.push_bind_unseparated(policy.as_db());
+        }
+        if let Some(latest) = self.latest_symlink {
+            fields.push("latest_symlink=");
+            fields.push_bind_unseparated(latest);
+        }
+        if let Some(pattern) = &self.visit_pattern {
+            fields.push("visit_pattern=");
+            fields.push_bind_unseparated(Some(pattern).filter(|p| !p.is_empty()));
+        }
+        if let Some(mode) = self.tracker_mode {
+            fields.push("tracker_mode=");
+            fields.push_bind_unseparated(mode.as_db());
+        }
+        if let Some(url) = &self.tracker_url {
+            fields.push("tracker_url=");
+            fields.push_bind_unseparated(url);
+        }
+        if let Some(root) = &self.detector_root {
+            fields.push("detector_root=");
+            fields.push_bind_unseparated(root.as_ref().map(|r| r.to_string()));
+        }
+        if let Some(length) = self.max_path_length {
+            fields.push("max_path_length=");
+            fields.push_bind_unseparated(Some(length).filter(|&len| len > 0));
+        }
+        if let Some(note) = &self.deprecation_note {
+            fields.push("deprecation_note=");
+            fields.push_bind_unseparated(Some(note).filter(|n| !n.is_empty()));
+        }
+        if let Some(scheme) = self.path_scheme {
+            fields.push("path_scheme=");
+            fields.push_bind_unseparated(scheme.as_db());
+        }
+        if let Some(base) = &self.base_uri {
+            fields.push("base_uri=");
+            fields.push_bind_unseparated(base);
+        }
+        if let Some(trackers) = &self.fallback_trackers {
+            fields.push("fallback_trackers=");
+            fields.push_bind_unseparated(TrackerMode::list_as_db(trackers));
+        }
+        if let Some(max) = self.max_acceptable_file_number {
+            fields.push("max_acceptable_file_number=");
+            fields.push_bind_unseparated(Some(max).filter(|&max| max > 0));
+        }
         q.push(" WHERE name = ");
         q.push_bind(&self.name);
         q.push(" RETURNING *");
@@ -176,11 +684,18 @@ impl InstrumentConfigurationUpdate {
             "Updating instrument configuration",
         );
 
-        q.build_query_as().fetch_optional(&db.pool).await
+        q.build_query_as().fetch_optional(conn).await
     }
+    #[cfg(test)]
     pub async fn insert_new(
         self,
         db: &SqliteScanPathService,
+    ) -> Result<InstrumentConfiguration, NewConfigurationError> {
+        self.insert_with(&mut *db.pool.acquire().await?).await
+    }
+    async fn insert_with(
+        self,
+        conn: &mut SqliteConnection,
     ) -> Result<InstrumentConfiguration, NewConfigurationError> {
         let dbc = DbInstrumentConfig {
             id: None,
@@ -190,11 +705,36 @@ impl InstrumentConfigurationUpdate {
             scan: self.scan.ok_or("scan")?.to_string(),
             detector: self.detector.ok_or("detector")?.to_string(),
             tracker_file_extension: self.tracker_file_extension,
+            use_tracker_file: self.use_tracker_file.unwrap_or(true),
+            scan_number_step: i64::from(self.scan_number_step.unwrap_or(1)),
+            scan_file_extension: self.scan_file_extension,
+            reset_policy: self.reset_policy.unwrap_or_default().as_db().into(),
+            last_allocated_year: None,
+            latest_symlink: self.latest_symlink.unwrap_or_default(),
+            visit_pattern: self.visit_pattern.filter(|p| !p.is_empty()),
+            tracker_mode: self.tracker_mode.unwrap_or_default().as_db().into(),
+            tracker_url: self.tracker_url,
+            detector_root: self.detector_root.flatten().map(|r| r.to_string()),
+            read_only: false,
+            max_path_length: self.max_path_length.filter(|&len| len > 0).map(i64::from),
+            deprecation_note: self.deprecation_note.filter(|n| !n.is_empty()),
+            // Not inserted, new configurations always start at the DB's default version
+            version: 1,
+            path_scheme: self.path_scheme.unwrap_or_default().as_db().into(),
+            base_uri: self.base_uri,
+            fallback_trackers: self
+                .fallback_trackers
+                .as_deref()
+                .and_then(TrackerMode::list_as_db),
+            max_acceptable_file_number: self
+                .max_acceptable_file_number
+                .filter(|&max| max > 0)
+                .map(i64::from),
         };
-        Ok(dbc.insert_into(db).await?)
+        Ok(dbc.insert_into(conn).await?)
     }
     #[cfg(test)]
-    fn empty(name: impl Into<String>) -> Self {
+    pub(crate) fn empty(name: impl Into<String>) -> Self {
         Self {
             name: name.into(),
             scan_number: None,
@@ -202,16 +742,46 @@ impl InstrumentConfigurationUpdate {
             scan: None,
             detector: None,
             tracker_file_extension: None,
+            use_tracker_file: None,
+            scan_number_step: None,
+            scan_file_extension: None,
+            reset_policy: None,
+            latest_symlink: None,
+            visit_pattern: None,
+            tracker_mode: None,
+            tracker_url: None,
+            detector_root: None,
+            max_path_length: None,
+            deprecation_note: None,
+            path_scheme: None,
+            base_uri: None,
+            fallback_trackers: None,
+            max_acceptable_file_number: None,
+            expected_version: None,
         }
     }
     #[cfg(test)]
-    fn with_scan_number(self, number: u32) -> Self {
+    pub(crate) fn with_scan_number(self, number: u32) -> Self {
         Self {
             scan_number: Some(number),
             ..self
         }
     }
     #[cfg(test)]
+    fn with_step(self, step: u32) -> Self {
+        Self {
+            scan_number_step: Some(step),
+            ..self
+        }
+    }
+    #[cfg(test)]
+    fn with_reset_policy(self, policy: ResetPolicy) -> Self {
+        Self {
+            reset_policy: Some(policy),
+            ..self
+        }
+    }
+    #[cfg(test)]
     fn with_extension(self, ext: &str) -> Self {
         Self {
             tracker_file_extension: Some(ext.into()),
@@ -230,56 +800,266 @@ struct DbInstrumentConfig {
     scan: String,
     detector: String,
     tracker_file_extension: Option<String>,
+    use_tracker_file: bool,
+    scan_number_step: i64,
+    scan_file_extension: Option<String>,
+    reset_policy: String,
+    #[allow(unused)] // only used within the DB to detect the start of a new year
+    last_allocated_year: Option<i64>,
+    latest_symlink: bool,
+    visit_pattern: Option<String>,
+    tracker_mode: String,
+    tracker_url: Option<String>,
+    detector_root: Option<String>,
+    read_only: bool,
+    max_path_length: Option<i64>,
+    deprecation_note: Option<String>,
+    version: i64,
+    path_scheme: String,
+    base_uri: Option<String>,
+    fallback_trackers: Option<String>,
+    max_acceptable_file_number: Option<i64>,
 }
 
 impl DbInstrumentConfig {
-    pub async fn insert_into(
+    async fn insert_into(
         self,
-        db: &SqliteScanPathService,
+        conn: &mut SqliteConnection,
     ) -> sqlx::Result<InstrumentConfiguration> {
         let bc = query_as!(
             DbInstrumentConfig,
             "INSERT INTO instrument
-                (name, scan_number, directory, scan, detector, tracker_file_extension)
+                (name, scan_number, directory, scan, detector, tracker_file_extension, use_tracker_file, scan_number_step, scan_file_extension, reset_policy, latest_symlink, visit_pattern, tracker_mode, tracker_url, detector_root, max_path_length, deprecation_note, path_scheme, base_uri, fallback_trackers, max_acceptable_file_number)
             VALUES
-                (?,?,?,?,?,?)
+                (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)
             RETURNING *",
             self.name,
             self.scan_number,
             self.directory,
             self.scan,
             self.detector,
-            self.tracker_file_extension
+            self.tracker_file_extension,
+            self.use_tracker_file,
+            self.scan_number_step,
+            self.scan_file_extension,
+            self.reset_policy,
+            self.latest_symlink,
+            self.visit_pattern,
+            self.tracker_mode,
+            self.tracker_url,
+            self.detector_root,
+            self.max_path_length,
+            self.deprecation_note,
+            self.path_scheme,
+            self.base_uri,
+            self.fallback_trackers,
+            self.max_acceptable_file_number
         )
-        .fetch_one(&db.pool)
+        .fetch_one(conn)
         .await?;
-        Ok(bc.into())
+        bc.try_into().map_err(|e| sqlx::Error::Decode(Box::new(e)))
     }
 }
 
-impl From<DbInstrumentConfig> for InstrumentConfiguration {
-    fn from(value: DbInstrumentConfig) -> Self {
-        Self {
+impl TryFrom<DbInstrumentConfig> for InstrumentConfiguration {
+    type Error = ConfigurationError;
+
+    fn try_from(value: DbInstrumentConfig) -> Result<Self, Self::Error> {
+        let Ok(scan_number) = u32::try_from(value.scan_number) else {
+            return Err(ConfigurationError::ScanNumberOverflow(value.name));
+        };
+        let Ok(scan_number_step) = u32::try_from(value.scan_number_step) else {
+            return Err(ConfigurationError::InvalidScanNumberStep(value.name));
+        };
+        let Ok(max_path_length) = value.max_path_length.map(u32::try_from).transpose() else {
+            return Err(ConfigurationError::InvalidMaxPathLength(value.name));
+        };
+        let Ok(max_acceptable_file_number) = value
+            .max_acceptable_file_number
+            .map(u32::try_from)
+            .transpose()
+        else {
+            return Err(ConfigurationError::InvalidMaxFileNumber(value.name));
+        };
+        Ok(Self {
             name: value.name,
-            scan_number: u32::try_from(value.scan_number).expect("Out of scan numbers"),
+            scan_number,
             directory: value.directory.into(),
             scan: value.scan.into(),
             detector: value.detector.into(),
             tracker_file_extension: value.tracker_file_extension,
-        }
+            use_tracker_file: value.use_tracker_file,
+            scan_number_step,
+            scan_file_extension: value.scan_file_extension,
+            reset_policy: ResetPolicy::from_db(&value.reset_policy),
+            latest_symlink: value.latest_symlink,
+            visit_pattern: value.visit_pattern,
+            tracker_mode: TrackerMode::from_db(&value.tracker_mode),
+            tracker_url: value.tracker_url,
+            detector_root: value.detector_root.map(Into::into),
+            read_only: value.read_only,
+            max_path_length,
+            deprecation_note: value.deprecation_note,
+            version: value.version,
+            path_scheme: PathScheme::from_db(&value.path_scheme),
+            base_uri: value.base_uri,
+            fallback_trackers: TrackerMode::list_from_db(value.fallback_trackers.as_deref()),
+            max_acceptable_file_number,
+        })
     }
 }
 
 impl SqliteScanPathService {
+    /// Connect to the DB, creating it if it does not exist
+    #[cfg(test)]
+    pub async fn connect(filename: &Path, size: PoolSize) -> Result<Self, sqlx::Error> {
+        Self::connect_with(filename, size, true).await
+    }
+
+    /// Connect to a DB that already exists, failing instead of creating a new one if it is
+    /// missing. Migrations are still run.
     #[instrument]
-    pub async fn connect(filename: &Path) -> Result<Self, sqlx::Error> {
+    pub async fn open_existing(filename: &Path, size: PoolSize) -> Result<Self, sqlx::Error> {
+        Self::connect_with(filename, size, false).await
+    }
+
+    async fn connect_with(
+        filename: &Path,
+        size: PoolSize,
+        create: bool,
+    ) -> Result<Self, sqlx::Error> {
         info!("Connecting to SQLite DB");
         let opts = SqliteConnectOptions::new()
-            .create_if_missing(true)
+            .create_if_missing(create)
             .filename(filename);
-        let pool = SqlitePool::connect_with(opts).await?;
+        let pool = SqlitePoolOptions::new()
+            .min_connections(size.min)
+            .max_connections(size.max)
+            .connect_with(opts)
+            .await?;
         sqlx::migrate!().run(&pool).await?;
-        Ok(Self { pool })
+        Ok(Self {
+            pool,
+            replica: None,
+            allocation_retention: None,
+        })
+    }
+
+    /// Connect to the DB, retrying with increasing delays if it fails. The error from the last
+    /// attempt is returned if every attempt fails.
+    ///
+    /// A DB file that is missing is retried in the same way, eg in case the volume containing it
+    /// has not been mounted over an existing mount point yet. Only the final attempt creates a new
+    /// DB if it is still missing.
+    pub async fn connect_with_retry(
+        filename: &Path,
+        size: PoolSize,
+        retry: ConnectRetry,
+    ) -> Result<Self, sqlx::Error> {
+        let mut attempt = 1;
+        loop {
+            let delay = retry.delay(attempt - 1);
+            let create = attempt > retry.retries;
+            match Self::connect_with(filename, size, create).await {
+                Ok(db) => return Ok(db),
+                Err(e) if attempt <= retry.retries => warn!(
+                    "Failed to connect to DB (attempt {attempt}/{}): {e}. Retrying in {delay:?}",
+                    retry.retries.saturating_add(1)
+                ),
+                Err(e) => return Err(e),
+            }
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Open an existing DB without being able to modify it. The DB is not created if it does not
+    /// exist and migrations are not run.
+    #[instrument]
+    pub async fn open_read_only(filename: &Path) -> Result<Self, sqlx::Error> {
+        info!("Opening SQLite DB read-only");
+        let opts = SqliteConnectOptions::new()
+            .read_only(true)
+            .filename(filename);
+        Ok(Self {
+            pool: SqlitePool::connect_with(opts).await?,
+            replica: None,
+            allocation_retention: None,
+        })
+    }
+
+    /// Send read-only queries to a replica of the DB instead of the primary DB.
+    ///
+    /// The replica is opened read-only and is expected to be kept up to date externally. It is
+    /// not created if it does not exist and migrations are not run against it.
+    #[instrument(skip(self))]
+    pub async fn with_replica(self, filename: &Path, size: PoolSize) -> Result<Self, sqlx::Error> {
+        info!("Connecting to SQLite DB replica");
+        let opts = SqliteConnectOptions::new()
+            .read_only(true)
+            .filename(filename);
+        let replica = SqlitePoolOptions::new()
+            .min_connections(size.min)
+            .max_connections(size.max)
+            .connect_with(opts)
+            .await?;
+        Ok(Self {
+            replica: Some(replica),
+            ..self
+        })
+    }
+
+    /// Only keep the most recent `retention` scan allocations for each instrument. Older
+    /// allocations are removed when a new scan is allocated.
+    pub fn with_allocation_retention(self, retention: Option<u32>) -> Self {
+        Self {
+            allocation_retention: retention,
+            ..self
+        }
+    }
+
+    /// A view of this service that only uses the primary DB. This should be used for reads that
+    /// need to see the most recent writes, eg when allocating a new scan number.
+    pub fn primary(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            replica: None,
+            allocation_retention: self.allocation_retention,
+        }
+    }
+
+    /// The pool used for read-only queries - the replica if there is one, otherwise the primary
+    fn reader(&self) -> &SqlitePool {
+        self.replica.as_ref().unwrap_or(&self.pool)
+    }
+
+    /// Open an existing DB in read-only mode and run SQLite's integrity check against it.
+    ///
+    /// Returns the list of problems found. An empty list means the DB is healthy. The DB is not
+    /// created if it does not exist and migrations are not run.
+    #[instrument]
+    pub async fn integrity_check(filename: &Path) -> Result<Vec<String>, sqlx::Error> {
+        info!("Checking SQLite DB");
+        let opts = SqliteConnectOptions::new()
+            .read_only(true)
+            .filename(filename);
+        let pool = SqlitePool::connect_with(opts).await?;
+        let problems = sqlx::query_scalar::<_, String>("PRAGMA integrity_check")
+            .fetch_all(&pool)
+            .await?
+            .into_iter()
+            .filter(|line| line != "ok")
+            .collect();
+        pool.close().await;
+        Ok(problems)
+    }
+
+    /// The version of the latest migration applied to the primary DB, or None if no migrations
+    /// have been applied
+    pub async fn migration_version(&self) -> Result<Option<i64>, sqlx::Error> {
+        sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+            .fetch_one(&self.pool)
+            .await
     }
 
     pub async fn current_configuration(
@@ -291,10 +1071,10 @@ impl SqliteScanPathService {
             "SELECT * FROM instrument WHERE name = ?",
             instrument
         )
-        .fetch_optional(&self.pool)
+        .fetch_optional(self.reader())
         .await?
-        .map(InstrumentConfiguration::from)
-        .ok_or(ConfigurationError::MissingInstrument(instrument.into()))
+        .ok_or(ConfigurationError::MissingInstrument(instrument.into()))?
+        .try_into()
     }
 
     pub async fn configurations(
@@ -309,51 +1089,345 @@ impl SqliteScanPathService {
         q.push(")");
 
         let query = q.build_query_as();
-        Ok(query.fetch_all(&self.pool).await?)
+        Ok(query.fetch_all(self.reader()).await?)
+    }
+
+    #[cfg(test)]
+    pub async fn all_configurations(
+        &self,
+    ) -> Result<Vec<InstrumentConfiguration>, ConfigurationError> {
+        self.all_configurations_stream().try_collect().await
+    }
+
+    /// Lazily read the configurations for all instruments
+    ///
+    /// Rows are read from the DB as the stream is polled rather than being collected up front so
+    /// that large numbers of instruments do not have to be held in memory at once.
+    pub fn all_configurations_stream(
+        &self,
+    ) -> impl Stream<Item = Result<InstrumentConfiguration, ConfigurationError>> + Send + '_ {
+        query_as!(DbInstrumentConfig, "SELECT * FROM instrument")
+            .fetch(self.reader())
+            .map_err(ConfigurationError::from)
+            .and_then(|config| ready(config.try_into()))
+    }
+
+    /// Allocate the next scan number for an instrument and record the subject it was allocated
+    /// to
+    ///
+    /// The year should be the current year and is used to reset the scan number of instruments
+    /// with a yearly [ResetPolicy].
+    #[cfg(test)]
+    pub async fn next_scan_configuration(
+        &self,
+        instrument: &str,
+        current_high: Option<u32>,
+        subject: Option<&str>,
+        year: i32,
+    ) -> Result<InstrumentConfiguration, ConfigurationError> {
+        self.begin_scan(instrument, current_high, subject, year)
+            .await?
+            .commit()
+            .await
+    }
+
+    /// Allocate the next scan number for an instrument without committing it to the DB. This
+    /// allows other work (eg updating tracker files) to be done before the allocation is
+    /// committed. Dropping the returned [PendingScan] rolls back the allocation.
+    ///
+    /// The DB is locked for writing until the pending scan is committed or dropped.
+    ///
+    /// If the instrument's scan number is reset yearly and this is the first scan allocated in
+    /// the given year, the scan number is reset to 1 and current_high is ignored. The check and
+    /// the reset are part of the same update so concurrent requests cannot both reset it.
+    ///
+    /// Scan numbers cannot be increased beyond `u32::MAX`. Once the limit is reached, every
+    /// allocation fails with [ConfigurationError::ScanNumberOverflow].
+    pub async fn begin_scan(
+        &self,
+        instrument: &str,
+        current_high: Option<u32>,
+        subject: Option<&str>,
+        year: i32,
+    ) -> Result<PendingScan, ConfigurationError> {
+        let exp = current_high.unwrap_or(0);
+        let mut tx = self.pool.begin().await?;
+        // If the new number doesn't fit in a u32, the conversion fails and returning drops the
+        // transaction so the increment is rolled back
+        let next: InstrumentConfiguration = query_as!(
+            DbInstrumentConfig,
+            "UPDATE instrument SET
+                scan_number = CASE
+                    WHEN reset_policy = 'YEARLY' AND last_allocated_year < ? THEN 1
+                    ELSE max(scan_number, ?) + scan_number_step
+                END,
+                last_allocated_year = ?
+            WHERE name = ? RETURNING *",
+            year,
+            exp,
+            year,
+            instrument
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(ConfigurationError::MissingInstrument(instrument.into()))?
+        .try_into()?;
+
+        let scan_number = next.scan_number();
+        let allocated_at = Utc::now().to_rfc3339();
+        query!(
+            "INSERT INTO scan_allocation (instrument, scan_number, subject, allocated_at) VALUES (?, ?, ?, ?)",
+            instrument,
+            scan_number,
+            subject,
+            allocated_at
+        )
+        .execute(&mut *tx)
+        .await?;
+        if let Some(retention) = self.allocation_retention {
+            // Remove everything older than the oldest allocation being kept. If there are fewer
+            // allocations than the retention, there is no such allocation and nothing is removed.
+            query!(
+                "DELETE FROM scan_allocation WHERE instrument = ? AND id <= (SELECT id FROM scan_allocation WHERE instrument = ? ORDER BY id DESC LIMIT 1 OFFSET ?)",
+                instrument,
+                instrument,
+                retention
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        Ok(PendingScan { tx, config: next })
+    }
+
+    /// Set the scan number for an instrument, eg when importing the state of an existing number
+    /// tracker. Unlike allocating a scan, this can lower the scan number and does not record an
+    /// allocation.
+    pub async fn set_scan_number(
+        &self,
+        instrument: &str,
+        number: u32,
+    ) -> Result<InstrumentConfiguration, ConfigurationError> {
+        query_as!(
+            DbInstrumentConfig,
+            "UPDATE instrument SET scan_number = ? WHERE name = ? RETURNING *",
+            number,
+            instrument
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(ConfigurationError::MissingInstrument(instrument.into()))?
+        .try_into()
+    }
+
+    /// Freeze or unfreeze an instrument. Read-only instruments can't allocate scan numbers or
+    /// have their configuration changed.
+    pub async fn set_read_only(
+        &self,
+        instrument: &str,
+        read_only: bool,
+    ) -> Result<InstrumentConfiguration, ConfigurationError> {
+        query_as!(
+            DbInstrumentConfig,
+            "UPDATE instrument SET read_only = ? WHERE name = ? RETURNING *",
+            read_only,
+            instrument
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(ConfigurationError::MissingInstrument(instrument.into()))?
+        .try_into()
+    }
+
+    /// Get the detector groups of an instrument, least recently changed first. Instruments
+    /// without groups (including those that don't exist) have no groups rather than returning an
+    /// error.
+    pub async fn detector_groups(
+        &self,
+        instrument: &str,
+    ) -> Result<Vec<DetectorGroup>, ConfigurationError> {
+        let members = query!(
+            "SELECT name, detector FROM detector_group WHERE instrument = ? ORDER BY id",
+            instrument
+        )
+        .fetch_all(self.reader())
+        .await?;
+        let mut groups = Vec::<DetectorGroup>::new();
+        for member in members {
+            match groups.iter_mut().find(|grp| grp.name == member.name) {
+                Some(group) => group.detectors.push(member.detector),
+                None => groups.push(DetectorGroup {
+                    name: member.name,
+                    detectors: vec![member.detector],
+                }),
+            }
+        }
+        Ok(groups)
+    }
+
+    /// Replace the members of an instrument's detector group, creating the group if it does not
+    /// already exist. An empty list of detectors removes the group.
+    pub async fn set_detector_group(
+        &self,
+        instrument: &str,
+        name: &str,
+        detectors: &[String],
+    ) -> Result<(), ConfigurationError> {
+        let mut tx = self.pool.begin().await?;
+        query!(
+            "DELETE FROM detector_group WHERE instrument = ? AND name = ?",
+            instrument,
+            name
+        )
+        .execute(&mut *tx)
+        .await?;
+        for detector in detectors {
+            query!(
+                "INSERT INTO detector_group (instrument, name, detector) VALUES (?, ?, ?)",
+                instrument,
+                name,
+                detector
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Remove the configuration of every instrument along with the record of allocated scans.
+    /// Both are removed in a single transaction so a failure leaves the DB unchanged. Returns the
+    /// number of instruments removed.
+    pub async fn reset_all(&self) -> sqlx::Result<u64> {
+        let mut tx = self.pool.begin().await?;
+        query!("DELETE FROM scan_allocation")
+            .execute(&mut *tx)
+            .await?;
+        query!("DELETE FROM detector_group")
+            .execute(&mut *tx)
+            .await?;
+        let removed = query!("DELETE FROM instrument")
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        tx.commit().await?;
+        Ok(removed)
+    }
+
+    /// Get the full history of scan numbers allocated for an instrument, oldest first
+    #[cfg(test)]
+    pub async fn scan_allocations(
+        &self,
+        instrument: &str,
+    ) -> Result<Vec<ScanAllocation>, ConfigurationError> {
+        Ok(query_as!(
+            ScanAllocation,
+            "SELECT scan_number as \"scan_number: u32\", subject, allocated_at FROM scan_allocation WHERE instrument = ? ORDER BY id",
+            instrument
+        )
+        .fetch_all(self.reader())
+        .await?)
     }
 
-    pub async fn all_configurations(
+    /// Get up to `limit` of the scan numbers allocated for an instrument, oldest first, skipping
+    /// the first `offset` allocations that are still recorded.
+    pub async fn scan_allocation_history(
         &self,
-    ) -> Result<Vec<InstrumentConfiguration>, ConfigurationError> {
-        Ok(query_as!(DbInstrumentConfig, "SELECT * FROM instrument")
-            .fetch_all(&self.pool)
-            .await?
-            .into_iter()
-            .map(InstrumentConfiguration::from)
-            .collect())
+        instrument: &str,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<ScanAllocation>, ConfigurationError> {
+        Ok(query_as!(
+            ScanAllocation,
+            "SELECT scan_number as \"scan_number: u32\", subject, allocated_at FROM scan_allocation WHERE instrument = ? ORDER BY id LIMIT ? OFFSET ?",
+            instrument,
+            limit,
+            offset
+        )
+        .fetch_all(self.reader())
+        .await?)
     }
 
-    pub async fn next_scan_configuration(
+    /// Get the most recent scan numbers allocated for an instrument, newest first. Instruments
+    /// with no allocations (including those that don't exist) have no history rather than
+    /// returning an error.
+    pub async fn recent_scan_allocations(
         &self,
         instrument: &str,
-        current_high: Option<u32>,
-    ) -> Result<InstrumentConfiguration, ConfigurationError> {
-        let exp = current_high.unwrap_or(0);
-        query_as!(
-            DbInstrumentConfig,
-            "UPDATE instrument SET scan_number = max(scan_number, ?) + 1 WHERE name = ? RETURNING *",
-            exp,
-            instrument
+        limit: u32,
+    ) -> Result<Vec<ScanAllocation>, ConfigurationError> {
+        Ok(query_as!(
+            ScanAllocation,
+            "SELECT scan_number as \"scan_number: u32\", subject, allocated_at FROM scan_allocation WHERE instrument = ? ORDER BY allocated_at DESC, id DESC LIMIT ?",
+            instrument,
+            limit
         )
-        .fetch_optional(&self.pool)
-        .await?
-        .map(InstrumentConfiguration::from)
-        .ok_or(ConfigurationError::MissingInstrument(instrument.into()))
+        .fetch_all(self.reader())
+        .await?)
     }
 
     /// Create a db service from a new empty/schema-less DB
     #[cfg(test)]
     pub(crate) async fn uninitialised() -> Self {
         Self {
-            pool: SqlitePool::connect(":memory:").await.unwrap(),
+            pool: Self::memory_pool().await,
+            replica: None,
+            allocation_retention: None,
         }
     }
 
+    /// Overwrite a column of an instrument's configuration without validating it, eg to mimic a
+    /// template that was stored before the current validation rules were added
+    #[cfg(test)]
+    pub(crate) async fn set_raw_value(&self, instrument: &str, column: &str, value: &str) {
+        sqlx::query(&format!(
+            "UPDATE instrument SET {column} = ? WHERE name = ?"
+        ))
+        .bind(value)
+        .bind(instrument)
+        .execute(&self.pool)
+        .await
+        .unwrap();
+    }
+
     #[cfg(test)]
     pub(crate) async fn memory() -> Self {
-        let pool = SqlitePool::connect(":memory:").await.unwrap();
+        let pool = Self::memory_pool().await;
         sqlx::migrate!().run(&pool).await.unwrap();
-        Self { pool }
+        Self {
+            pool,
+            replica: None,
+            allocation_retention: None,
+        }
+    }
+
+    /// Each connection to an in-memory DB gets its own independent DB so the pool is limited to a
+    /// single connection to ensure that every query sees the same data.
+    #[cfg(test)]
+    async fn memory_pool() -> SqlitePool {
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap()
+    }
+}
+
+/// A scan number allocation that has not yet been committed to the DB
+pub struct PendingScan {
+    tx: Transaction<'static, Sqlite>,
+    config: InstrumentConfiguration,
+}
+
+impl PendingScan {
+    /// The instrument configuration including the newly allocated scan number
+    pub fn config(&self) -> &InstrumentConfiguration {
+        &self.config
+    }
+
+    /// Commit the allocation to the DB
+    pub async fn commit(self) -> Result<InstrumentConfiguration, ConfigurationError> {
+        self.tx.commit().await?;
+        Ok(self.config)
     }
 }
 
@@ -363,6 +1437,13 @@ impl fmt::Debug for SqliteScanPathService {
         // information when debugging the state of the service
         f.debug_struct("SqliteScanPathService")
             .field("db", &self.pool.connect_options().get_filename())
+            .field(
+                "replica",
+                &self
+                    .replica
+                    .as_ref()
+                    .map(|r| r.connect_options().get_filename().to_owned()),
+            )
             .finish()
     }
 }
@@ -376,6 +1457,21 @@ mod error {
         MissingInstrument(#[error(ignore)] String),
         #[display("Error reading configuration: {_0}")]
         Db(sqlx::Error),
+        #[display(
+            "Scan number for instrument {_0:?} would exceed the maximum of {}",
+            u32::MAX
+        )]
+        #[from(ignore)]
+        ScanNumberOverflow(#[error(ignore)] String),
+        #[display("Scan number step for instrument {_0:?} is not valid")]
+        #[from(ignore)]
+        InvalidScanNumberStep(#[error(ignore)] String),
+        #[display("Maximum path length for instrument {_0:?} is not valid")]
+        #[from(ignore)]
+        InvalidMaxPathLength(#[error(ignore)] String),
+        #[display("Maximum tracker file number for instrument {_0:?} is not valid")]
+        #[from(ignore)]
+        InvalidMaxFileNumber(#[error(ignore)] String),
     }
 
     #[derive(Debug, Display, From)]
@@ -385,6 +1481,14 @@ mod error {
         #[from]
         #[display("Error inserting new configuration: {_0}")]
         Db(sqlx::Error),
+        #[display(
+            "Configuration for {instrument:?} is at version {current}, not the expected version {expected}"
+        )]
+        Conflict {
+            instrument: String,
+            expected: i64,
+            current: i64,
+        },
     }
 
     impl From<&str> for NewConfigurationError {
@@ -396,15 +1500,27 @@ mod error {
 
 #[cfg(test)]
 mod db_tests {
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use futures::TryStreamExt as _;
     use rstest::rstest;
     use sqlx::error::{DatabaseError as _, ErrorKind};
     use sqlx::sqlite::SqliteError;
     use tokio::test;
 
-    use super::SqliteScanPathService;
+    use super::{ConnectRetry, PoolSize, SqliteScanPathService};
     use crate::db_service::error::{ConfigurationError, NewConfigurationError};
-    use crate::db_service::{InstrumentConfiguration, InstrumentConfigurationUpdate};
-    use crate::paths::{DetectorTemplate, DirectoryTemplate, PathSpec, ScanTemplate};
+    use crate::db_service::{
+        DetectorGroup, InstrumentConfiguration, InstrumentConfigurationUpdate, PathScheme,
+        ResetPolicy, ScanAllocation, TrackerMode,
+    };
+    use crate::paths::{
+        DetectorTemplate, DirectoryTemplate, InvalidPathTemplate, PathSpec, ScanTemplate,
+    };
+
+    /// The year used when allocating scans in tests that don't depend on the date
+    const YEAR: i32 = 2024;
 
     /// Remove repeated .await.unwrap() noise from tests
     macro_rules! ok {
@@ -438,6 +1554,22 @@ mod db_tests {
             )
             .ok(),
             tracker_file_extension: None,
+            use_tracker_file: None,
+            scan_number_step: None,
+            scan_file_extension: None,
+            reset_policy: None,
+            latest_symlink: None,
+            visit_pattern: None,
+            tracker_mode: None,
+            tracker_url: None,
+            detector_root: None,
+            max_path_length: None,
+            deprecation_note: None,
+            path_scheme: None,
+            base_uri: None,
+            fallback_trackers: None,
+            max_acceptable_file_number: None,
+            expected_version: None,
         }
     }
 
@@ -504,8 +1636,8 @@ mod db_tests {
     async fn incrementing_scan_numbers() {
         let db = SqliteScanPathService::memory().await;
         ok!(update("i22").insert_new(&db));
-        let s1 = ok!(db.next_scan_configuration("i22", None));
-        let s2 = ok!(db.next_scan_configuration("i22", None));
+        let s1 = ok!(db.next_scan_configuration("i22", None, None, YEAR));
+        let s2 = ok!(db.next_scan_configuration("i22", None, None, YEAR));
         assert_eq!(s1.scan_number() + 1, s2.scan_number());
     }
 
@@ -513,28 +1645,363 @@ mod db_tests {
     async fn overriding_scan_number_updates_db() {
         let db = SqliteScanPathService::memory().await;
         ok!(update("i22").with_scan_number(122).insert_new(&db));
-        let s1 = ok!(db.next_scan_configuration("i22", None));
-        let s2 = ok!(db.next_scan_configuration("i22", Some(1234)));
-        let s3 = ok!(db.next_scan_configuration("i22", None));
+        let s1 = ok!(db.next_scan_configuration("i22", None, None, YEAR));
+        let s2 = ok!(db.next_scan_configuration("i22", Some(1234), None, YEAR));
+        let s3 = ok!(db.next_scan_configuration("i22", None, None, YEAR));
         assert_eq!(s1.scan_number(), 123);
         assert_eq!(s2.scan_number(), 1235);
         assert_eq!(s3.scan_number(), 1236);
     }
 
+    #[test]
+    async fn stepped_scan_numbers() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22")
+            .with_scan_number(120)
+            .with_step(10)
+            .insert_new(&db));
+        let s1 = ok!(db.next_scan_configuration("i22", None, None, YEAR));
+        let s2 = ok!(db.next_scan_configuration("i22", None, None, YEAR));
+        assert_eq!(s1.scan_number(), 130);
+        assert_eq!(s2.scan_number(), 140);
+        assert_eq!(s2.scan_number_step(), 10);
+    }
+
+    #[test]
+    async fn stepped_scan_numbers_with_override() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22")
+            .with_scan_number(120)
+            .with_step(10)
+            .insert_new(&db));
+        // Higher file numbers are used as the base for the next step
+        let s1 = ok!(db.next_scan_configuration("i22", Some(1234), None, YEAR));
+        // Lower file numbers are ignored
+        let s2 = ok!(db.next_scan_configuration("i22", Some(42), None, YEAR));
+        assert_eq!(s1.scan_number(), 1244);
+        assert_eq!(s2.scan_number(), 1254);
+    }
+
+    #[test]
+    async fn invalid_scan_number_step() {
+        let db = SqliteScanPathService::memory().await;
+        err!(update("i22").with_step(0).insert_new(&db));
+        ok!(update("i22").insert_new(&db));
+        err!(InstrumentConfigurationUpdate::empty("i22")
+            .with_step(0)
+            .update_instrument(&db));
+        assert_eq!(ok!(db.current_configuration("i22")).scan_number_step(), 1);
+    }
+
+    #[test]
+    async fn scan_number_overflow() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22").with_scan_number(u32::MAX - 1).insert_new(&db));
+        let s1 = ok!(db.next_scan_configuration("i22", None, None, YEAR));
+        assert_eq!(s1.scan_number(), u32::MAX);
+        let name = err!(
+            ConfigurationError::ScanNumberOverflow,
+            db.next_scan_configuration("i22", None, None, YEAR)
+        );
+        assert_eq!(name, "i22");
+        // The failed allocation is rolled back
+        assert_eq!(ok!(db.current_configuration("i22")).scan_number(), u32::MAX);
+        assert_eq!(ok!(db.scan_allocations("i22")).len(), 1);
+    }
+
+    #[test]
+    async fn scan_number_overflow_from_tracker_file() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22").insert_new(&db));
+        err!(
+            ConfigurationError::ScanNumberOverflow,
+            db.next_scan_configuration("i22", Some(u32::MAX), None, YEAR)
+        );
+        assert_eq!(ok!(db.current_configuration("i22")).scan_number(), 0);
+    }
+
+    #[test]
+    async fn out_of_range_scan_number() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22").insert_new(&db));
+        // Not possible via the service but could be set by editing the DB directly
+        sqlx::query("UPDATE instrument SET scan_number = ? WHERE name = 'i22'")
+            .bind(i64::from(u32::MAX) + 1)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let name = err!(
+            ConfigurationError::ScanNumberOverflow,
+            db.current_configuration("i22")
+        );
+        assert_eq!(name, "i22");
+    }
+
     #[test]
     async fn lower_scan_override_is_ignored() {
         let db = SqliteScanPathService::memory().await;
         ok!(update("i22").with_scan_number(122).insert_new(&db));
-        let s1 = ok!(db.next_scan_configuration("i22", Some(42)));
+        let s1 = ok!(db.next_scan_configuration("i22", Some(42), None, YEAR));
         assert_eq!(s1.scan_number(), 123);
     }
 
+    #[test]
+    async fn yearly_reset() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22")
+            .with_scan_number(122)
+            .with_reset_policy(ResetPolicy::Yearly)
+            .insert_new(&db));
+        assert_eq!(
+            ok!(db.current_configuration("i22")).reset_policy(),
+            ResetPolicy::Yearly
+        );
+        // The first allocation has no previous year to compare against so is not reset
+        let s1 = ok!(db.next_scan_configuration("i22", None, None, 2024));
+        let s2 = ok!(db.next_scan_configuration("i22", None, None, 2024));
+        // Only the first allocation in a new year is reset and the tracker file number is ignored
+        let s3 = ok!(db.next_scan_configuration("i22", Some(1234), None, 2025));
+        let s4 = ok!(db.next_scan_configuration("i22", None, None, 2025));
+        // An earlier year (eg from a clock change) does not reset the number
+        let s5 = ok!(db.next_scan_configuration("i22", None, None, 2024));
+        assert_eq!(
+            [s1, s2, s3, s4, s5].map(|s| s.scan_number()),
+            [123, 124, 1, 2, 3]
+        );
+    }
+
+    #[test]
+    async fn reset_is_rolled_back_with_pending_scan() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22")
+            .with_scan_number(122)
+            .with_reset_policy(ResetPolicy::Yearly)
+            .insert_new(&db));
+        ok!(db.next_scan_configuration("i22", None, None, 2024));
+        let pending = ok!(db.begin_scan("i22", None, None, 2025));
+        assert_eq!(pending.config().scan_number(), 1);
+        drop(pending);
+        assert_eq!(ok!(db.current_configuration("i22")).scan_number(), 123);
+        let s1 = ok!(db.next_scan_configuration("i22", None, None, 2025));
+        assert_eq!(s1.scan_number(), 1);
+    }
+
+    #[test]
+    async fn never_reset_by_default() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22").with_scan_number(122).insert_new(&db));
+        assert_eq!(
+            ok!(db.current_configuration("i22")).reset_policy(),
+            ResetPolicy::Never
+        );
+        ok!(db.next_scan_configuration("i22", None, None, 2024));
+        let s1 = ok!(db.next_scan_configuration("i22", None, None, 2025));
+        assert_eq!(s1.scan_number(), 124);
+
+        // Changing the policy applies from the next new year
+        ok!(InstrumentConfigurationUpdate::empty("i22")
+            .with_reset_policy(ResetPolicy::Yearly)
+            .update_instrument(&db));
+        let s2 = ok!(db.next_scan_configuration("i22", None, None, 2025));
+        let s3 = ok!(db.next_scan_configuration("i22", None, None, 2026));
+        assert_eq!(s2.scan_number(), 125);
+        assert_eq!(s3.scan_number(), 1);
+    }
+
+    #[test]
+    async fn set_read_only() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22").insert_new(&db));
+        assert!(!ok!(db.current_configuration("i22")).read_only());
+        assert!(ok!(db.set_read_only("i22", true)).read_only());
+        assert!(ok!(db.current_configuration("i22")).read_only());
+        assert!(!ok!(db.set_read_only("i22", false)).read_only());
+        err!(
+            ConfigurationError::MissingInstrument,
+            db.set_read_only("b21", true)
+        );
+    }
+
+    #[test]
+    async fn set_scan_number() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22").with_scan_number(122).insert_new(&db));
+        assert_eq!(ok!(db.set_scan_number("i22", 42)).scan_number(), 42);
+        assert_eq!(ok!(db.current_configuration("i22")).scan_number(), 42);
+        assert!(ok!(db.scan_allocations("i22")).is_empty());
+        err!(
+            ConfigurationError::MissingInstrument,
+            db.set_scan_number("b21", 42)
+        );
+    }
+
+    #[test]
+    async fn dropped_pending_scan_is_rolled_back() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22").with_scan_number(122).insert_new(&db));
+        let pending = ok!(db.begin_scan("i22", None, Some("user"), YEAR));
+        assert_eq!(pending.config().scan_number(), 123);
+        drop(pending);
+        assert_eq!(ok!(db.current_configuration("i22")).scan_number(), 122);
+        assert!(ok!(db.scan_allocations("i22")).is_empty());
+
+        let pending = ok!(db.begin_scan("i22", None, Some("user"), YEAR));
+        assert_eq!(ok!(pending.commit()).scan_number(), 123);
+        assert_eq!(ok!(db.current_configuration("i22")).scan_number(), 123);
+        assert_eq!(ok!(db.scan_allocations("i22")).len(), 1);
+    }
+
+    #[test]
+    async fn allocations_are_recorded() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22").with_scan_number(122).insert_new(&db));
+        ok!(update("b21").insert_new(&db));
+        ok!(db.next_scan_configuration("i22", None, Some("user_one"), YEAR));
+        ok!(db.next_scan_configuration("b21", None, Some("user_one"), YEAR));
+        ok!(db.next_scan_configuration("i22", None, None, YEAR));
+
+        let history = ok!(db.scan_allocations("i22"));
+        let history = history
+            .iter()
+            .map(|a| (a.scan_number(), a.subject()))
+            .collect::<Vec<_>>();
+        assert_eq!(history, [(123, Some("user_one")), (124, None)]);
+    }
+
+    #[test]
+    async fn recent_allocations() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22").with_scan_number(122).insert_new(&db));
+        ok!(update("b21").insert_new(&db));
+        for _ in 0..5 {
+            ok!(db.next_scan_configuration("i22", None, None, YEAR));
+        }
+        ok!(db.next_scan_configuration("b21", None, None, YEAR));
+
+        let recent = ok!(db.recent_scan_allocations("i22", 3));
+        let numbers = recent.iter().map(|a| a.scan_number()).collect::<Vec<_>>();
+        assert_eq!(numbers, [127, 126, 125]);
+        assert!(recent
+            .windows(2)
+            .all(|pair| pair[0].allocated_at() >= pair[1].allocated_at()));
+
+        // Limits larger than the history return everything
+        assert_eq!(ok!(db.recent_scan_allocations("i22", 10)).len(), 5);
+        assert_eq!(ok!(db.recent_scan_allocations("b21", 10)).len(), 1);
+    }
+
+    #[test]
+    async fn allocation_history_pages() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22").with_scan_number(122).insert_new(&db));
+        for _ in 0..5 {
+            ok!(db.next_scan_configuration("i22", None, None, YEAR));
+        }
+        let numbers =
+            |page: Vec<ScanAllocation>| page.iter().map(|a| a.scan_number()).collect::<Vec<_>>();
+        assert_eq!(
+            numbers(ok!(db.scan_allocation_history("i22", 2, 0))),
+            [123, 124]
+        );
+        assert_eq!(numbers(ok!(db.scan_allocation_history("i22", 2, 4))), [127]);
+        assert!(ok!(db.scan_allocation_history("i22", 2, 5)).is_empty());
+        assert!(ok!(db.scan_allocation_history("b21", 2, 0)).is_empty());
+    }
+
+    #[test]
+    async fn allocation_retention() {
+        let db = SqliteScanPathService::memory()
+            .await
+            .with_allocation_retention(Some(3));
+        ok!(update("i22").with_scan_number(122).insert_new(&db));
+        ok!(update("b21").insert_new(&db));
+        ok!(db.next_scan_configuration("b21", None, None, YEAR));
+        for _ in 0..5 {
+            ok!(db.next_scan_configuration("i22", None, None, YEAR));
+        }
+        let history = ok!(db.scan_allocations("i22"));
+        let numbers = history.iter().map(|a| a.scan_number()).collect::<Vec<_>>();
+        assert_eq!(numbers, [125, 126, 127]);
+        // Other instruments' allocations are not counted or removed
+        assert_eq!(ok!(db.scan_allocations("b21")).len(), 1);
+    }
+
+    #[test]
+    async fn reset_all() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22").with_scan_number(122).insert_new(&db));
+        ok!(update("b21").insert_new(&db));
+        ok!(db.next_scan_configuration("i22", None, None, YEAR));
+        ok!(db.set_detector_group("i22", "all", &["det1".into()]));
+
+        assert_eq!(ok!(db.reset_all()), 2);
+        assert!(ok!(db.all_configurations()).is_empty());
+        assert!(ok!(db.scan_allocations("i22")).is_empty());
+        assert!(ok!(db.detector_groups("i22")).is_empty());
+
+        // Instruments can be configured again from scratch
+        ok!(update("i22").insert_new(&db));
+        assert_eq!(ok!(db.current_configuration("i22")).scan_number(), 0);
+    }
+
+    #[test]
+    async fn detector_groups() {
+        let db = SqliteScanPathService::memory().await;
+        let names = |names: &[&str]| names.iter().map(|&n| n.into()).collect::<Vec<String>>();
+        let group = |name: &str, detectors: &[&str]| DetectorGroup {
+            name: name.into(),
+            detectors: names(detectors),
+        };
+        assert!(ok!(db.detector_groups("i22")).is_empty());
+
+        ok!(db.set_detector_group("i22", "eiger", &names(&["mod1", "mod2", "mod3"])));
+        ok!(db.set_detector_group("i22", "pilatus", &names(&["saxs", "waxs"])));
+        ok!(db.set_detector_group("b21", "eiger", &names(&["mod1"])));
+        assert_eq!(
+            ok!(db.detector_groups("i22")),
+            [
+                group("eiger", &["mod1", "mod2", "mod3"]),
+                group("pilatus", &["saxs", "waxs"])
+            ]
+        );
+
+        // Replacing a group's members only affects that group
+        ok!(db.set_detector_group("i22", "eiger", &names(&["mod4", "mod1"])));
+        let groups = ok!(db.detector_groups("i22"));
+        assert_eq!(groups[0].name(), "pilatus");
+        assert_eq!(groups[1].name(), "eiger");
+        assert_eq!(groups[1].detectors(), ["mod4", "mod1"]);
+        assert_eq!(ok!(db.detector_groups("b21")), [group("eiger", &["mod1"])]);
+
+        // An empty group is removed
+        ok!(db.set_detector_group("i22", "pilatus", &[]));
+        assert_eq!(
+            ok!(db.detector_groups("i22")),
+            [group("eiger", &["mod4", "mod1"])]
+        );
+    }
+
+    #[test]
+    async fn recent_allocations_without_history() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22").insert_new(&db));
+        assert!(ok!(db.recent_scan_allocations("i22", 10)).is_empty());
+        assert!(ok!(db.recent_scan_allocations("b21", 10)).is_empty());
+    }
+
+    #[test]
+    async fn failed_allocation_is_not_recorded() {
+        let db = SqliteScanPathService::memory().await;
+        err!(db.next_scan_configuration("b21", None, Some("user_one"), YEAR));
+        assert!(ok!(db.scan_allocations("b21")).is_empty());
+    }
+
     #[test]
     async fn incrementing_missing_instrument() {
         let db = SqliteScanPathService::memory().await;
         let e = err!(
             ConfigurationError::MissingInstrument,
-            db.next_scan_configuration("b21", None)
+            db.next_scan_configuration("b21", None, None, YEAR)
         );
         assert_eq!(e, "b21")
     }
@@ -554,6 +2021,23 @@ mod db_tests {
             scan: "{subdirectory}/{instrument}-{scan_number}".into(),
             detector: "{subdirectory}/{instrument}-{scan_number}-{detector}".into(),
             tracker_file_extension: Some("ext".into()),
+            use_tracker_file: true,
+            scan_number_step: 1,
+            scan_file_extension: None,
+            reset_policy: ResetPolicy::Never,
+            latest_symlink: false,
+            visit_pattern: None,
+            tracker_mode: TrackerMode::File,
+            tracker_url: None,
+            detector_root: None,
+            read_only: false,
+            max_path_length: None,
+            deprecation_note: None,
+            version: 1,
+            path_scheme: PathScheme::File,
+            base_uri: None,
+            fallback_trackers: vec![],
+            max_acceptable_file_number: None,
         };
         assert_eq!(conf, expected);
     }
@@ -588,6 +2072,23 @@ mod db_tests {
                 scan: "{subdirectory}/{instrument}-{scan_number}".into(),
                 detector: "{subdirectory}/{instrument}-{scan_number}-{detector}".into(),
                 tracker_file_extension: Some("ext".into()),
+                use_tracker_file: true,
+                scan_number_step: 1,
+                scan_file_extension: None,
+                reset_policy: ResetPolicy::Never,
+                latest_symlink: false,
+                visit_pattern: None,
+                tracker_mode: TrackerMode::File,
+                tracker_url: None,
+                detector_root: None,
+                read_only: false,
+                max_path_length: None,
+                deprecation_note: None,
+                version: 1,
+                path_scheme: PathScheme::File,
+                base_uri: None,
+                fallback_trackers: vec![],
+                max_acceptable_file_number: None,
             },
             InstrumentConfiguration {
                 name: "i22".into(),
@@ -596,6 +2097,23 @@ mod db_tests {
                 scan: "{subdirectory}/{instrument}-{scan_number}".into(),
                 detector: "{subdirectory}/{instrument}-{scan_number}-{detector}".into(),
                 tracker_file_extension: Some("ext".into()),
+                use_tracker_file: true,
+                scan_number_step: 1,
+                scan_file_extension: None,
+                reset_policy: ResetPolicy::Never,
+                latest_symlink: false,
+                visit_pattern: None,
+                tracker_mode: TrackerMode::File,
+                tracker_url: None,
+                detector_root: None,
+                read_only: false,
+                max_path_length: None,
+                deprecation_note: None,
+                version: 1,
+                path_scheme: PathScheme::File,
+                base_uri: None,
+                fallback_trackers: vec![],
+                max_acceptable_file_number: None,
             },
         ];
         assert_eq!(expected, confs);
@@ -628,6 +2146,23 @@ mod db_tests {
                 scan: "{subdirectory}/{instrument}-{scan_number}".into(),
                 detector: "{subdirectory}/{instrument}-{scan_number}-{detector}".into(),
                 tracker_file_extension: Some("ext".into()),
+                use_tracker_file: true,
+                scan_number_step: 1,
+                scan_file_extension: None,
+                reset_policy: ResetPolicy::Never,
+                latest_symlink: false,
+                visit_pattern: None,
+                tracker_mode: TrackerMode::File,
+                tracker_url: None,
+                detector_root: None,
+                read_only: false,
+                max_path_length: None,
+                deprecation_note: None,
+                version: 1,
+                path_scheme: PathScheme::File,
+                base_uri: None,
+                fallback_trackers: vec![],
+                max_acceptable_file_number: None,
             },
             InstrumentConfiguration {
                 name: "i22".into(),
@@ -636,11 +2171,172 @@ mod db_tests {
                 scan: "{subdirectory}/{instrument}-{scan_number}".into(),
                 detector: "{subdirectory}/{instrument}-{scan_number}-{detector}".into(),
                 tracker_file_extension: Some("ext".into()),
+                use_tracker_file: true,
+                scan_number_step: 1,
+                scan_file_extension: None,
+                reset_policy: ResetPolicy::Never,
+                latest_symlink: false,
+                visit_pattern: None,
+                tracker_mode: TrackerMode::File,
+                tracker_url: None,
+                detector_root: None,
+                read_only: false,
+                max_path_length: None,
+                deprecation_note: None,
+                version: 1,
+                path_scheme: PathScheme::File,
+                base_uri: None,
+                fallback_trackers: vec![],
+                max_acceptable_file_number: None,
             },
         ];
         assert_eq!(expected, confs);
     }
 
+    #[test]
+    async fn all_configurations_stream() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22").with_scan_number(122).insert_new(&db));
+        ok!(update("i11").with_scan_number(111).insert_new(&db));
+
+        let mut names = ok!(db
+            .all_configurations_stream()
+            .map_ok(|conf| conf.name().to_string())
+            .try_collect::<Vec<_>>());
+        names.sort_unstable();
+
+        assert_eq!(names, ["i11", "i22"]);
+    }
+
+    /// Create a DB containing i22 in a new directory, to be moved into place as if a volume
+    /// containing it was mounted
+    async fn staged_db(root: &Path) -> PathBuf {
+        let staging = root.join("staging");
+        std::fs::create_dir(&staging).unwrap();
+        let db =
+            SqliteScanPathService::connect(&staging.join("numtracker.db"), PoolSize::default())
+                .await
+                .unwrap();
+        ok!(update("i22").insert_new(&db));
+        db.pool.close().await;
+        staging
+    }
+
+    #[rstest]
+    #[case::missing_directory(false)]
+    #[case::missing_file(true)]
+    #[tokio::test]
+    async fn connect_retries_until_available(#[case] mount_point: bool) {
+        let root = tempfile::tempdir().unwrap();
+        let staging = staged_db(root.path()).await;
+        let dir = root.path().join("volume");
+        if mount_point {
+            std::fs::create_dir(&dir).unwrap();
+        }
+        let retry = ConnectRetry {
+            retries: 10,
+            interval: Duration::from_millis(5),
+        };
+        // The DB is only available after the first attempts have failed
+        let mount = tokio::spawn({
+            let dir = dir.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                let db = dir.join("numtracker.db");
+                tokio::fs::create_dir_all(dir).await.unwrap();
+                tokio::fs::rename(staging.join("numtracker.db"), db)
+                    .await
+                    .unwrap();
+            }
+        });
+        let db = SqliteScanPathService::connect_with_retry(
+            &dir.join("numtracker.db"),
+            PoolSize::default(),
+            retry,
+        )
+        .await;
+        mount.await.unwrap();
+        let db = db.unwrap();
+        // The mounted DB is used instead of a new empty one
+        ok!(db.current_configuration("i22"));
+    }
+
+    #[test]
+    async fn connect_creates_missing_db_after_retries() {
+        let root = tempfile::tempdir().unwrap();
+        let retry = ConnectRetry {
+            retries: 2,
+            interval: Duration::from_millis(1),
+        };
+        let file = root.path().join("numtracker.db");
+        let db = SqliteScanPathService::connect_with_retry(&file, PoolSize::default(), retry).await;
+        assert!(db.is_ok(), "Failed to connect: {:?}", db.err());
+        assert!(file.exists());
+    }
+
+    #[test]
+    async fn connect_retries_give_up() {
+        let root = tempfile::tempdir().unwrap();
+        let retry = ConnectRetry {
+            retries: 2,
+            interval: Duration::from_millis(1),
+        };
+        let db = SqliteScanPathService::connect_with_retry(
+            &root.path().join("missing").join("numtracker.db"),
+            PoolSize::default(),
+            retry,
+        )
+        .await;
+        assert!(db.is_err(), "Connected to DB in missing directory");
+    }
+
+    #[rstest]
+    #[case::first(0, Duration::from_secs(1))]
+    #[case::doubled(3, Duration::from_secs(8))]
+    #[case::capped(5, ConnectRetry::MAX_INTERVAL)]
+    #[case::overflow(u32::MAX, ConnectRetry::MAX_INTERVAL)]
+    #[tokio::test]
+    async fn connect_retry_delay(#[case] retry: u32, #[case] expected: Duration) {
+        let retry_opts = ConnectRetry {
+            retries: u32::MAX,
+            interval: Duration::from_secs(1),
+        };
+        assert_eq!(retry_opts.delay(retry), expected);
+    }
+
+    #[test]
+    async fn invalid_templates() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22").insert_new(&db));
+        assert!(ok!(db.current_configuration("i22"))
+            .invalid_templates()
+            .is_empty());
+
+        // Templates stored before the current rules were in place
+        db.set_raw_value("i22", "scan", "/abs/{scan_number}").await;
+        db.set_raw_value("i22", "detector_root", "fast/{visit}")
+            .await;
+        let conf = ok!(db.current_configuration("i22"));
+        let invalid = conf.invalid_templates();
+        assert_eq!(invalid.len(), 2);
+        assert!(matches!(
+            invalid[0],
+            (
+                "scan",
+                "/abs/{scan_number}",
+                InvalidPathTemplate::ShouldBeRelative
+            )
+        ));
+        assert!(matches!(
+            invalid[1],
+            (
+                "detectorRoot",
+                "fast/{visit}",
+                InvalidPathTemplate::ShouldBeAbsolute
+            )
+        ));
+    }
+
     type Update = InstrumentConfigurationUpdate;
 
     #[rstest]
@@ -659,6 +2355,42 @@ mod db_tests {
     #[case::extension(
             |u: &mut Update| u.tracker_file_extension = Some("new".into()),
             |u: InstrumentConfiguration| assert_eq!(u.tracker_file_extension.unwrap(), "new"))]
+    #[case::scan_number_step(
+            |u: &mut Update| u.scan_number_step = Some(5),
+            |u: InstrumentConfiguration| assert_eq!(u.scan_number_step(), 5))]
+    #[case::use_tracker_file(
+            |u: &mut Update| u.use_tracker_file = Some(false),
+            |u: InstrumentConfiguration| assert!(!u.use_tracker_file()))]
+    #[case::scan_file_extension(
+            |u: &mut Update| u.scan_file_extension = Some("nxs".into()),
+            |u: InstrumentConfiguration| assert_eq!(u.scan_file_extension(), Some("nxs")))]
+    #[case::latest_symlink(
+            |u: &mut Update| u.latest_symlink = Some(true),
+            |u: InstrumentConfiguration| assert!(u.latest_symlink()))]
+    #[case::visit_pattern(
+            |u: &mut Update| u.visit_pattern = Some("[a-z]{2}[0-9]+-[0-9]+".into()),
+            |u: InstrumentConfiguration| assert_eq!(u.visit_pattern(), Some("[a-z]{2}[0-9]+-[0-9]+")))]
+    #[case::tracker_mode(
+            |u: &mut Update| u.tracker_mode = Some(TrackerMode::Http),
+            |u: InstrumentConfiguration| assert_eq!(u.tracker_mode(), TrackerMode::Http))]
+    #[case::tracker_url(
+            |u: &mut Update| u.tracker_url = Some("http://tracker.example.com/i22".into()),
+            |u: InstrumentConfiguration| assert_eq!(u.tracker_url(), Some("http://tracker.example.com/i22")))]
+    #[case::detector_root(
+            |u: &mut Update| u.detector_root = Some(DirectoryTemplate::new_checked("/fast/{instrument}/{visit}").ok()),
+            |u: InstrumentConfiguration| assert_eq!(u.detector_root().unwrap().unwrap().to_string(), "/fast/{instrument}/{visit}"))]
+    #[case::max_path_length(
+            |u: &mut Update| u.max_path_length = Some(100),
+            |u: InstrumentConfiguration| assert_eq!(u.max_path_length(), Some(100)))]
+    #[case::deprecation_note(
+            |u: &mut Update| u.deprecation_note = Some("Use the new layout".into()),
+            |u: InstrumentConfiguration| assert_eq!(u.deprecation_note(), Some("Use the new layout")))]
+    #[case::fallback_trackers(
+            |u: &mut Update| u.fallback_trackers = Some(vec![TrackerMode::Http, TrackerMode::File]),
+            |u: InstrumentConfiguration| assert_eq!(u.fallback_trackers(), [TrackerMode::Http, TrackerMode::File]))]
+    #[case::max_acceptable_file_number(
+            |u: &mut Update| u.max_acceptable_file_number = Some(10_000),
+            |u: InstrumentConfiguration| assert_eq!(u.max_acceptable_file_number(), Some(10_000)))]
     #[tokio::test]
     async fn update_existing(
         #[case] init: impl FnOnce(&mut InstrumentConfigurationUpdate),
@@ -672,6 +2404,166 @@ mod db_tests {
         check(bc)
     }
 
+    #[test]
+    async fn stale_version_update() {
+        let db = SqliteScanPathService::memory().await;
+        let conf = ok!(update("i22").insert_new(&db));
+        assert_eq!(conf.version(), 1);
+
+        // Both updates are based on version 1 but only the first can be applied
+        let mut first = InstrumentConfigurationUpdate::empty("i22");
+        first.scan_number = Some(200);
+        first.expected_version = Some(1);
+        assert_eq!(ok!(first.apply(&db)).version(), 2);
+
+        let mut second = InstrumentConfigurationUpdate::empty("i22");
+        second.scan_number = Some(300);
+        second.expected_version = Some(1);
+        let NewConfigurationError::Conflict {
+            instrument,
+            expected,
+            current,
+        } = err!(second.apply(&db))
+        else {
+            panic!("Stale update was not rejected as a conflict");
+        };
+        assert_eq!((instrument.as_str(), expected, current), ("i22", 1, 2));
+        let conf = ok!(db.current_configuration("i22"));
+        assert_eq!((conf.scan_number(), conf.version()), (200, 2));
+
+        // Updates without an expected version are applied regardless
+        let mut blind = InstrumentConfigurationUpdate::empty("i22");
+        blind.scan_number = Some(300);
+        assert_eq!(ok!(blind.apply(&db)).version(), 3);
+
+        // Instruments that don't exist yet are at version 0
+        let mut new = update("i11");
+        new.expected_version = Some(1);
+        let e = err!(new.apply(&db));
+        assert!(
+            matches!(e, NewConfigurationError::Conflict { current: 0, .. }),
+            "Unexpected error: {e}"
+        );
+        let mut new = update("i11");
+        new.expected_version = Some(0);
+        assert_eq!(ok!(new.apply(&db)).version(), 1);
+    }
+
+    #[test]
+    async fn integrity_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("numtracker.db");
+        let db = ok!(SqliteScanPathService::connect(&file, PoolSize::default()));
+        ok!(update("i22").insert_new(&db));
+        let problems = ok!(SqliteScanPathService::integrity_check(&file));
+        assert!(problems.is_empty(), "Unexpected problems: {problems:?}");
+    }
+
+    #[test]
+    async fn migration_version() {
+        let db = SqliteScanPathService::memory().await;
+        let version = ok!(db.migration_version());
+        // Each migration is embedded as separate up and down scripts
+        let migrations = sqlx::migrate!()
+            .iter()
+            .filter(|m| m.migration_type.is_up_migration())
+            .map(|m| m.version)
+            .collect::<Vec<_>>();
+        assert_eq!(version, Some(migrations.len() as i64));
+        assert_eq!(version, migrations.into_iter().max());
+    }
+
+    #[test]
+    async fn integrity_check_missing_db() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("missing.db");
+        err!(SqliteScanPathService::integrity_check(&file));
+        assert!(!file.exists(), "Check should not create a new DB");
+    }
+
+    #[test]
+    async fn concurrent_reads_with_small_pool() {
+        let dir = tempfile::tempdir().unwrap();
+        let size = PoolSize { min: 1, max: 2 };
+        let db = ok!(SqliteScanPathService::connect(
+            &dir.path().join("numtracker.db"),
+            size
+        ));
+        ok!(update("i22").with_scan_number(122).insert_new(&db));
+        let reads =
+            futures::future::join_all((0..8).map(|_| db.current_configuration("i22"))).await;
+        for conf in reads {
+            assert_eq!(conf.unwrap().scan_number(), 122);
+        }
+    }
+
+    #[test]
+    async fn reads_use_replica() {
+        let dir = tempfile::tempdir().unwrap();
+        let replica = dir.path().join("replica.db");
+        let db = ok!(SqliteScanPathService::connect(
+            &replica,
+            PoolSize::default()
+        ));
+        ok!(update("i22").with_scan_number(10).insert_new(&db));
+
+        let db = ok!(SqliteScanPathService::connect(
+            &dir.path().join("primary.db"),
+            PoolSize::default()
+        ));
+        ok!(update("i22").with_scan_number(122).insert_new(&db));
+        let db = ok!(db.with_replica(&replica, PoolSize::default()));
+
+        // Reads see the (stale) replica
+        assert_eq!(ok!(db.current_configuration("i22")).scan_number(), 10);
+        assert_eq!(ok!(db.all_configurations())[0].scan_number(), 10);
+        // Writes always go to the primary
+        assert_eq!(
+            ok!(db.next_scan_configuration("i22", None, None, YEAR)).scan_number(),
+            123
+        );
+        assert_eq!(
+            ok!(db.primary().current_configuration("i22")).scan_number(),
+            123
+        );
+        assert_eq!(ok!(db.current_configuration("i22")).scan_number(), 10);
+    }
+
+    #[test]
+    async fn writes_ignore_replica() {
+        let dir = tempfile::tempdir().unwrap();
+        let replica = dir.path().join("replica.db");
+        let db = ok!(SqliteScanPathService::connect(
+            &replica,
+            PoolSize::default()
+        ));
+        ok!(update("i22").insert_new(&db));
+        let replica = ok!(SqliteScanPathService::connect(
+            &dir.path().join("primary.db"),
+            PoolSize::default()
+        )
+        .await
+        .unwrap()
+        .with_replica(&replica, PoolSize::default()));
+        // Only the replica has an instrument so writes that used it would succeed
+        err!(
+            ConfigurationError::MissingInstrument,
+            replica.next_scan_configuration("i22", None, None, YEAR)
+        );
+    }
+
+    #[test]
+    async fn tracker_file_used_by_default() {
+        let db = SqliteScanPathService::memory().await;
+        ok!(update("i22").insert_new(&db));
+        assert!(ok!(db.current_configuration("i22")).use_tracker_file());
+
+        let mut upd = update("i11");
+        upd.use_tracker_file = Some(false);
+        ok!(upd.insert_new(&db));
+        assert!(!ok!(db.current_configuration("i11")).use_tracker_file());
+    }
+
     #[tokio::test]
     async fn empty_update() {
         let db = SqliteScanPathService::memory().await;
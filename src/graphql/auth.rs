@@ -12,16 +12,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock};
 
 use async_graphql::{Error, ErrorExtensions};
 use axum_extra::headers::authorization::Bearer;
 use axum_extra::headers::Authorization;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
 use derive_more::{Display, Error, From};
+use reqwest::header::HeaderMap;
 use serde::{Deserialize, Serialize};
+use tokio::fs;
 use tracing::info;
 
 use crate::cli::PolicyOptions;
+use crate::paths::parse_session;
 
 const AUDIENCE: &str = "account";
 
@@ -35,7 +42,21 @@ struct Request<T> {
 #[derive(Debug, Deserialize)]
 #[cfg_attr(test, derive(Serialize))]
 struct Response {
-    result: bool,
+    result: Decision,
+}
+
+/// The result of a policy query. Policies can either return a bare bool or an object with an
+/// `allow` field and an optional reason for denying access.
+#[derive(Debug, Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+#[serde(untagged)]
+enum Decision {
+    Bool(bool),
+    Detailed {
+        allow: bool,
+        #[serde(default)]
+        reason: Option<String>,
+    },
 }
 
 #[derive(Debug, Serialize)]
@@ -99,47 +120,119 @@ impl FromStr for InstrumentSession {
     type Err = InvalidInstrumentSession;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (code_prop, vis) = s.split_once('-').ok_or(InvalidInstrumentSession)?;
-        let prop = code_prop
-            .chars()
-            .skip_while(|p| !p.is_ascii_digit())
-            .collect::<String>();
-        let proposal = prop.parse().map_err(|_| InvalidInstrumentSession)?;
-        let session = vis.parse().map_err(|_| InvalidInstrumentSession)?;
-        Ok(Self { proposal, session })
+        let parts = parse_session(s).ok_or(InvalidInstrumentSession)?;
+        Ok(Self {
+            proposal: parts.proposal,
+            session: parts.session,
+        })
     }
 }
 
-pub(crate) struct PolicyCheck {
-    client: reqwest::Client,
+/// The full URLs of the rego queries used to check authorization
+struct Endpoints {
     /// Rego query for getting admin rights
     admin: String,
     /// Rego query for getting access rights
     access: String,
+    /// Static headers added to every query
+    headers: HeaderMap,
+    /// The options the queries were built from
+    options: PolicyOptions,
 }
 
-impl PolicyCheck {
-    pub fn new(endpoint: PolicyOptions) -> Self {
+impl From<PolicyOptions> for Endpoints {
+    fn from(endpoint: PolicyOptions) -> Self {
         info!(
             "Checking authorization against {:?} using {:?} for admin and {:?} for access",
             endpoint.policy_host, endpoint.admin_query, endpoint.access_query
         );
+        if !endpoint.policy_headers.is_empty() {
+            info!(
+                "Adding headers {:?} to policy requests",
+                endpoint
+                    .policy_headers
+                    .iter()
+                    .map(|hdr| hdr.name.as_str())
+                    .collect::<Vec<_>>()
+            );
+        }
         Self {
-            client: reqwest::Client::new(),
             admin: format!("{}/{}", endpoint.policy_host, endpoint.admin_query),
             access: format!("{}/{}", endpoint.policy_host, endpoint.access_query),
+            headers: endpoint
+                .policy_headers
+                .iter()
+                .map(|hdr| (hdr.name.clone(), hdr.value.clone()))
+                .collect(),
+            options: endpoint,
+        }
+    }
+}
+
+pub(crate) struct PolicyCheck {
+    client: reqwest::Client,
+    /// The current queries to use. Each check takes its own reference so that replacing the
+    /// endpoints does not affect checks that are already in progress.
+    endpoints: RwLock<Arc<Endpoints>>,
+    /// The file the policy options were read from, if any
+    source: Option<PathBuf>,
+}
+
+impl PolicyCheck {
+    pub fn new(endpoint: PolicyOptions) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            endpoints: RwLock::new(Arc::new(endpoint.into())),
+            source: None,
         }
     }
+
+    /// Create a policy check using options read from a file. The same file is re-read whenever
+    /// the policy is reloaded.
+    pub async fn from_file(path: PathBuf) -> Result<Self, PolicyFileError> {
+        let options = read_policy_file(&path).await?;
+        Ok(Self {
+            source: Some(path),
+            ..Self::new(options)
+        })
+    }
+
+    /// Re-read the policy options from the file this check was created from
+    pub async fn reload(&self) -> Result<(), PolicyFileError> {
+        let source = self.source.as_ref().ok_or(PolicyFileError::NoFile)?;
+        let endpoints = read_policy_file(source).await?.into();
+        *self
+            .endpoints
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Arc::new(endpoints);
+        Ok(())
+    }
+
+    /// The policy options currently being used to check authorization
+    pub fn options(&self) -> PolicyOptions {
+        self.endpoints().options.clone()
+    }
+
+    fn endpoints(&self) -> Arc<Endpoints> {
+        self.endpoints
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
     pub async fn check_access(
         &self,
         token: Option<&Authorization<Bearer>>,
         instrument: &str,
         instrument_session: &str,
     ) -> Result<(), AuthError> {
-        let session: InstrumentSession =
-            instrument_session.parse().map_err(|_| AuthError::Failed)?;
+        let session: InstrumentSession = instrument_session
+            .parse()
+            .map_err(|_| AuthError::Failed(None))?;
+        let endpoints = self.endpoints();
         self.authorise(
-            &self.access,
+            &endpoints,
+            &endpoints.access,
             AccessRequest::new(token, session, instrument)?,
         )
         .await
@@ -149,8 +242,13 @@ impl PolicyCheck {
         &self,
         token: Option<&Authorization<Bearer>>,
     ) -> Result<(), AuthError> {
-        self.authorise(&self.admin, AdminRequest::new(token, None)?)
-            .await
+        let endpoints = self.endpoints();
+        self.authorise(
+            &endpoints,
+            &endpoints.admin,
+            AdminRequest::new(token, None)?,
+        )
+        .await
     }
 
     pub async fn check_instrument_admin(
@@ -158,31 +256,76 @@ impl PolicyCheck {
         token: Option<&Authorization<Bearer>>,
         instrument: &str,
     ) -> Result<(), AuthError> {
-        self.authorise(&self.admin, AdminRequest::new(token, Some(instrument))?)
-            .await
+        let endpoints = self.endpoints();
+        self.authorise(
+            &endpoints,
+            &endpoints.admin,
+            AdminRequest::new(token, Some(instrument))?,
+        )
+        .await
     }
 
-    async fn authorise(&self, query: &str, input: impl Serialize) -> Result<(), AuthError> {
+    async fn authorise(
+        &self,
+        endpoints: &Endpoints,
+        query: &str,
+        input: impl Serialize,
+    ) -> Result<(), AuthError> {
         let response = self
             .client
             .post(query)
+            .headers(endpoints.headers.clone())
             .json(&Request { input })
             .send()
             .await?;
-        if response.json::<Response>().await?.result {
-            Ok(())
-        } else {
-            Err(AuthError::Failed)
+        match response.json::<Response>().await?.result {
+            Decision::Bool(true) | Decision::Detailed { allow: true, .. } => Ok(()),
+            Decision::Bool(false) => Err(AuthError::Failed(None)),
+            Decision::Detailed { reason, .. } => Err(AuthError::Failed(reason)),
         }
     }
 }
 
+/// Read the subject claim from a JWT access token
+///
+/// The signature is not verified here so this should only be used for tokens that have already
+/// been accepted by the policy service.
+pub fn token_subject(token: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Claims {
+        sub: Option<String>,
+    }
+    let payload = token.split('.').nth(1)?;
+    let json = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+    serde_json::from_slice::<Claims>(&json).ok()?.sub
+}
+
+async fn read_policy_file(path: &Path) -> Result<PolicyOptions, PolicyFileError> {
+    info!("Reading policy options from {path:?}");
+    let src = fs::read_to_string(path).await?;
+    Ok(toml::from_str(&src)?)
+}
+
+#[derive(Debug, Display, Error, From)]
+pub enum PolicyFileError {
+    #[display("Authorization is not enabled")]
+    NotConfigured,
+    #[display("Policy was not loaded from a file")]
+    NoFile,
+    #[display("Policy file could not be read: {_0}")]
+    Unreadable(std::io::Error),
+    #[display("Policy file was invalid: {_0}")]
+    Invalid(toml::de::Error),
+}
+
 #[derive(Debug, Display, Error, From)]
 pub enum AuthError {
     #[display("Invalid authorization configuration")]
     ServerError(reqwest::Error),
-    #[display("Authentication failed")]
-    Failed,
+    /// Access was denied, with the reason given by the policy if there was one
+    #[display("Authentication failed{}", _0.as_ref().map(|r| format!(": {r}")).unwrap_or_default())]
+    #[from(ignore)]
+    Failed(#[error(ignore)] Option<String>),
     #[display("No authentication token was provided")]
     Missing,
 }
@@ -191,7 +334,12 @@ impl ErrorExtensions for AuthError {
     fn extend(&self) -> Error {
         self.extend_with(|err, e| match err {
             AuthError::ServerError(_) => e.set("code", "AUTH_SERVER_ERROR"),
-            AuthError::Failed => e.set("code", "AUTH_FAILED"),
+            AuthError::Failed(reason) => {
+                e.set("code", "AUTH_FAILED");
+                if let Some(reason) = reason {
+                    e.set("reason", reason.as_str());
+                }
+            }
             AuthError::Missing => e.set("code", "AUTH_MISSING"),
         })
     }
@@ -206,12 +354,17 @@ mod tests {
     use axum::http::HeaderValue;
     use axum_extra::headers::authorization::{Bearer, Credentials};
     use axum_extra::headers::Authorization;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine as _;
     use httpmock::MockServer;
     use reqwest::Client;
     use rstest::rstest;
     use serde_json::json;
 
-    use super::{AuthError, InstrumentSession, InvalidInstrumentSession, PolicyCheck};
+    use super::{
+        token_subject, AuthError, InstrumentSession, InvalidInstrumentSession, PolicyCheck,
+        PolicyFileError,
+    };
     use crate::cli::PolicyOptions;
 
     fn token(name: &'static str) -> Option<Authorization<Bearer>> {
@@ -240,6 +393,21 @@ mod tests {
         )
     }
 
+    #[rstest]
+    #[case::subject(r#"{"sub": "abc123"}"#, Some("abc123"))]
+    #[case::no_subject(r#"{"aud": "account"}"#, None)]
+    #[case::invalid_json("not json", None)]
+    fn jwt_subject(#[case] claims: &str, #[case] expected: Option<&str>) {
+        let token = format!("header.{}.signature", URL_SAFE_NO_PAD.encode(claims));
+        assert_eq!(token_subject(&token).as_deref(), expected);
+    }
+
+    #[test]
+    fn non_jwt_subject() {
+        assert_eq!(token_subject("opaque_token"), None);
+        assert_eq!(token_subject("opaque.!!invalid base64!!.token"), None);
+    }
+
     #[tokio::test]
     async fn successful_check_access() {
         let server = MockServer::start();
@@ -263,6 +431,7 @@ mod tests {
             policy_host: server.url(""),
             access_query: "demo/access".into(),
             admin_query: "demo/admin".into(),
+            ..Default::default()
         });
         check
             .check_access(token("token").as_ref(), "i22", "cm1234-4")
@@ -292,6 +461,7 @@ mod tests {
             policy_host: server.url(""),
             access_query: "demo/access".into(),
             admin_query: "demo/admin".into(),
+            ..Default::default()
         });
         check
             .check_instrument_admin(token("token").as_ref(), "i22")
@@ -320,6 +490,7 @@ mod tests {
             policy_host: server.url(""),
             access_query: "demo/access".into(),
             admin_query: "demo/admin".into(),
+            ..Default::default()
         });
         check.check_admin(token("token").as_ref()).await.unwrap();
         mock.assert();
@@ -348,12 +519,13 @@ mod tests {
             policy_host: server.url(""),
             access_query: "demo/access".into(),
             admin_query: "demo/admin".into(),
+            ..Default::default()
         });
 
         let result = check
             .check_access(token("token").as_ref(), "i22", "cm1234-4")
             .await;
-        let Err(AuthError::Failed) = result else {
+        let Err(AuthError::Failed(None)) = result else {
             panic!("Unexpected result from unauthorised check: {result:?}");
         };
         mock.assert();
@@ -380,12 +552,13 @@ mod tests {
             policy_host: server.url(""),
             access_query: "demo/access".into(),
             admin_query: "demo/admin".into(),
+            ..Default::default()
         });
         let result = check
             .check_instrument_admin(token("token").as_ref(), "i22")
             .await;
 
-        let Err(AuthError::Failed) = result else {
+        let Err(AuthError::Failed(None)) = result else {
             panic!("Unexpected result from unauthorised check: {result:?}");
         };
         mock.assert();
@@ -411,15 +584,53 @@ mod tests {
             policy_host: server.url(""),
             access_query: "demo/access".into(),
             admin_query: "demo/admin".into(),
+            ..Default::default()
         });
         let result = check.check_admin(token("token").as_ref()).await;
 
-        let Err(AuthError::Failed) = result else {
+        let Err(AuthError::Failed(None)) = result else {
             panic!("Unexpected result from unauthorised check: {result:?}");
         };
         mock.assert();
     }
 
+    #[rstest]
+    #[case::bool_allowed(json!({"result": true}), Ok(()))]
+    #[case::bool_denied(json!({"result": false}), Err(None))]
+    #[case::object_allowed(json!({"result": {"allow": true}}), Ok(()))]
+    #[case::object_allowed_with_reason(json!({"result": {"allow": true, "reason": "admin"}}), Ok(()))]
+    #[case::object_denied(json!({"result": {"allow": false}}), Err(None))]
+    #[case::object_denied_with_reason(
+        json!({"result": {"allow": false, "reason": "Not an admin"}}),
+        Err(Some("Not an admin"))
+    )]
+    #[tokio::test]
+    async fn policy_response_formats(
+        #[case] response: serde_json::Value,
+        #[case] expected: Result<(), Option<&str>>,
+    ) {
+        let server = MockServer::start();
+        let mock = server
+            .mock_async(|when, then| {
+                when.method("POST").path("/demo/admin");
+                then.status(200).json_body_obj(&response);
+            })
+            .await;
+        let check = PolicyCheck::new(PolicyOptions {
+            policy_host: server.url(""),
+            access_query: "demo/access".into(),
+            admin_query: "demo/admin".into(),
+            ..Default::default()
+        });
+        let result = check.check_admin(token("token").as_ref()).await;
+        match (result, expected) {
+            (Ok(()), Ok(())) => {}
+            (Err(AuthError::Failed(reason)), Err(exp)) => assert_eq!(reason.as_deref(), exp),
+            (result, expected) => panic!("Expected {expected:?} but got {result:?}"),
+        }
+        mock.assert();
+    }
+
     #[tokio::test]
     async fn unauthorised_check_access() {
         let server = MockServer::start();
@@ -432,6 +643,7 @@ mod tests {
             policy_host: server.url(""),
             access_query: "demo/access".into(),
             admin_query: "demo/admin".into(),
+            ..Default::default()
         });
         let result = check.check_access(None, "i22", "cm1234-4").await;
 
@@ -453,6 +665,7 @@ mod tests {
             policy_host: server.url(""),
             access_query: "demo/access".into(),
             admin_query: "demo/admin".into(),
+            ..Default::default()
         });
         let result = check.check_instrument_admin(None, "i22").await;
 
@@ -474,6 +687,7 @@ mod tests {
             policy_host: server.url(""),
             access_query: "demo/access".into(),
             admin_query: "demo/admin".into(),
+            ..Default::default()
         });
         let result = check.check_admin(None).await;
 
@@ -496,6 +710,7 @@ mod tests {
             policy_host: server.url(""),
             access_query: "demo/access".into(),
             admin_query: "demo/admin".into(),
+            ..Default::default()
         });
         let result = check
             .check_instrument_admin(token("token").as_ref(), "i22")
@@ -507,9 +722,103 @@ mod tests {
         mock.assert();
     }
 
+    #[tokio::test]
+    async fn policy_headers() {
+        let server = MockServer::start();
+        let access = server
+            .mock_async(|when, then| {
+                when.method("POST")
+                    .path("/demo/access")
+                    .header("X-Tenant", "diamond")
+                    .header("X-Api-Key", "secret");
+                then.status(200).json_body_obj(&json!({"result": true}));
+            })
+            .await;
+        let admin = server
+            .mock_async(|when, then| {
+                when.method("POST")
+                    .path("/demo/admin")
+                    .header("X-Tenant", "diamond")
+                    .header("X-Api-Key", "secret");
+                then.status(200).json_body_obj(&json!({"result": true}));
+            })
+            .await;
+        let check = PolicyCheck::new(PolicyOptions {
+            policy_host: server.url(""),
+            access_query: "demo/access".into(),
+            admin_query: "demo/admin".into(),
+            policy_headers: vec![
+                "X-Tenant=diamond".parse().unwrap(),
+                "X-Api-Key=secret".parse().unwrap(),
+            ],
+        });
+        check
+            .check_access(token("token").as_ref(), "i22", "cm1234-4")
+            .await
+            .unwrap();
+        check.check_admin(token("token").as_ref()).await.unwrap();
+        check
+            .check_instrument_admin(token("token").as_ref(), "i22")
+            .await
+            .unwrap();
+        access.assert();
+        admin.assert_calls(2);
+    }
+
+    #[tokio::test]
+    async fn reload_policy_file() {
+        let server = MockServer::start();
+        let old = server
+            .mock_async(|when, then| {
+                when.method("POST").path("/demo/admin");
+                then.status(200).json_body_obj(&json!({"result": true}));
+            })
+            .await;
+        let new = server
+            .mock_async(|when, then| {
+                when.method("POST").path("/demo/new_admin");
+                then.status(200).json_body_obj(&json!({"result": true}));
+            })
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("policy.toml");
+        let write_policy = |admin: &str| {
+            std::fs::write(
+                &file,
+                format!(
+                    "policy_host = {:?}\naccess_query = \"demo/access\"\nadmin_query = {admin:?}\n",
+                    server.url("")
+                ),
+            )
+            .unwrap()
+        };
+
+        write_policy("demo/admin");
+        let check = PolicyCheck::from_file(file.clone()).await.unwrap();
+        check.check_admin(token("token").as_ref()).await.unwrap();
+
+        write_policy("demo/new_admin");
+        check.reload().await.unwrap();
+        check.check_admin(token("token").as_ref()).await.unwrap();
+
+        old.assert_calls(1);
+        new.assert_calls(1);
+    }
+
+    #[tokio::test]
+    async fn reload_without_file() {
+        let check = PolicyCheck::new(PolicyOptions {
+            policy_host: "http://opa.example.com".into(),
+            access_query: "demo/access".into(),
+            admin_query: "demo/admin".into(),
+            ..Default::default()
+        });
+        assert_matches!(check.reload().await, Err(PolicyFileError::NoFile));
+    }
+
     #[rstest]
     #[case::server_error(AuthError::ServerError(Client::new().get("invalid").build().unwrap_err()), "AUTH_SERVER_ERROR")]
-    #[case::failed(AuthError::Failed, "AUTH_FAILED")]
+    #[case::failed(AuthError::Failed(None), "AUTH_FAILED")]
     #[case::missing(AuthError::Missing, "AUTH_MISSING")]
     #[tokio::test]
 
@@ -520,4 +829,16 @@ mod tests {
 
         assert_eq!(code, &Value::String(expected))
     }
+
+    #[test]
+    fn denial_reason_is_reported() {
+        let e = AuthError::Failed(Some("Not an admin".into())).extend();
+        assert_eq!(e.message, "Authentication failed: Not an admin");
+        let extensions = e.extensions.expect("Error should have extensions");
+        assert_eq!(
+            extensions.get("reason"),
+            Some(&Value::String("Not an admin".into()))
+        );
+        assert_eq!(AuthError::Failed(None).to_string(), "Authentication failed");
+    }
 }
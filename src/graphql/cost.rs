@@ -0,0 +1,72 @@
+// Copyright 2024 Diamond Light Source
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, Mutex, PoisonError};
+
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextRequest, NextValidation,
+};
+use async_graphql::{async_trait, value, Response, ServerError, ValidationResult};
+
+/// Request data added when the client has asked for the cost of its query
+pub struct DebugCost;
+
+/// Extension that adds the depth and complexity of a query to the response extensions when the
+/// request includes [DebugCost] data. Requests without it are passed through unchanged.
+pub struct QueryCost;
+
+impl ExtensionFactory for QueryCost {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(QueryCostExtension::default())
+    }
+}
+
+#[derive(Default)]
+struct QueryCostExtension {
+    result: Mutex<Option<ValidationResult>>,
+}
+
+#[async_trait::async_trait]
+impl Extension for QueryCostExtension {
+    async fn request(&self, ctx: &ExtensionContext<'_>, next: NextRequest<'_>) -> Response {
+        let resp = next.run(ctx).await;
+        let result = self
+            .result
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        match result {
+            Some(result) => resp.extension(
+                "cost",
+                value!({
+                    "complexity": result.complexity,
+                    "depth": result.depth,
+                }),
+            ),
+            None => resp,
+        }
+    }
+
+    async fn validation(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        next: NextValidation<'_>,
+    ) -> Result<ValidationResult, Vec<ServerError>> {
+        let result = next.run(ctx).await?;
+        if ctx.data_opt::<DebugCost>().is_some() {
+            *self.result.lock().unwrap_or_else(PoisonError::into_inner) = Some(result);
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright 2024 Diamond Light Source
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::{Arc, Mutex, PoisonError};
+
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextPrepareRequest, NextRequest,
+};
+use async_graphql::{async_trait, value, Request, Response, ServerResult, Value};
+
+/// Extension that adds the deprecation notes of any instruments whose templates were used to
+/// build a response to the response extensions, so that clients can warn users without the
+/// request failing.
+pub struct Deprecations;
+
+impl ExtensionFactory for Deprecations {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(DeprecationExtension::default())
+    }
+}
+
+/// Request data used by resolvers to record the instruments with deprecated templates that they
+/// used. It is only present if the [Deprecations] extension is enabled.
+#[derive(Debug, Clone, Default)]
+pub struct DeprecationNotes(Arc<Mutex<Vec<(String, String)>>>);
+
+impl DeprecationNotes {
+    /// Record the note for an instrument. Each instrument is only reported once per request.
+    pub fn add(&self, instrument: &str, note: &str) {
+        let mut notes = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if !notes.iter().any(|(inst, _)| inst == instrument) {
+            notes.push((instrument.into(), note.into()));
+        }
+    }
+
+    fn take(&self) -> Vec<(String, String)> {
+        std::mem::take(&mut *self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+#[derive(Default)]
+struct DeprecationExtension {
+    notes: DeprecationNotes,
+}
+
+#[async_trait::async_trait]
+impl Extension for DeprecationExtension {
+    async fn request(&self, ctx: &ExtensionContext<'_>, next: NextRequest<'_>) -> Response {
+        let resp = next.run(ctx).await;
+        let notes = self.notes.take();
+        if notes.is_empty() {
+            return resp;
+        }
+        resp.extension(
+            "deprecations",
+            Value::List(
+                notes
+                    .into_iter()
+                    .map(|(instrument, note)| value!({"instrument": instrument, "note": note}))
+                    .collect(),
+            ),
+        )
+    }
+
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> ServerResult<Request> {
+        next.run(ctx, request.data(self.notes.clone())).await
+    }
+}
@@ -0,0 +1,190 @@
+// Copyright 2024 Diamond Light Source
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::Serialize;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::cli::redact;
+
+/// Message published when a new scan number has been allocated
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanEvent {
+    pub instrument: String,
+    pub visit: String,
+    pub scan_number: u32,
+    pub paths: ScanEventPaths,
+}
+
+/// The absolute paths of a newly allocated scan
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanEventPaths {
+    pub directory: String,
+    pub scan_file: String,
+}
+
+/// Handle used to publish [ScanEvent]s to an external service without waiting for them to be
+/// delivered.
+///
+/// Events are queued in a bounded buffer and sent in order by a background task. If the buffer is
+/// full, new events are dropped so that a slow or unavailable service cannot hold up allocating
+/// scans.
+#[derive(Debug, Clone)]
+pub struct ScanPublisher {
+    queue: Sender<ScanEvent>,
+}
+
+impl ScanPublisher {
+    /// Create a publisher that POSTs each event as JSON to the given URL. This must be called
+    /// from within a tokio runtime.
+    pub fn webhook(url: Url, buffer: usize) -> Self {
+        info!("Publishing scan events to {}", redact(url.to_string()));
+        let (queue, events) = mpsc::channel(buffer);
+        tokio::spawn(send_webhooks(reqwest::Client::new(), url, events));
+        Self { queue }
+    }
+
+    /// Queue an event to be published. Failures are logged but not returned as there is nothing
+    /// the client requesting the scan can do about them.
+    pub fn publish(&self, event: ScanEvent) {
+        match self.queue.try_send(event) {
+            Ok(()) => {}
+            Err(TrySendError::Full(event)) => warn!(
+                instrument = event.instrument,
+                scan_number = event.scan_number,
+                "Scan event buffer is full - dropping event"
+            ),
+            Err(TrySendError::Closed(event)) => warn!(
+                instrument = event.instrument,
+                scan_number = event.scan_number,
+                "Scan event publisher has stopped - dropping event"
+            ),
+        }
+    }
+}
+
+/// Send each queued event to the webhook until every [ScanPublisher] has been dropped
+async fn send_webhooks(client: reqwest::Client, url: Url, mut events: Receiver<ScanEvent>) {
+    while let Some(event) = events.recv().await {
+        let sent = client
+            .post(url.clone())
+            .json(&event)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status());
+        match sent {
+            Ok(_) => debug!(
+                instrument = event.instrument,
+                scan_number = event.scan_number,
+                "Published scan event"
+            ),
+            Err(e) => warn!(
+                instrument = event.instrument,
+                scan_number = event.scan_number,
+                "Failed to publish scan event: {e}"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use httpmock::MockServer;
+    use serde_json::json;
+
+    use super::{ScanEvent, ScanEventPaths, ScanPublisher};
+
+    fn event(scan_number: u32) -> ScanEvent {
+        ScanEvent {
+            instrument: "i22".into(),
+            visit: "cm12345-3".into(),
+            scan_number,
+            paths: ScanEventPaths {
+                directory: "/tmp/i22/data/cm12345-3".into(),
+                scan_file: format!("/tmp/i22/data/cm12345-3/i22-{scan_number}"),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn failures_do_not_stop_publishing() {
+        let server = MockServer::start_async().await;
+        let failing = server
+            .mock_async(|when, then| {
+                when.method("POST").path("/events").json_body_obj(&json!({
+                    "instrument": "i22",
+                    "visit": "cm12345-3",
+                    "scanNumber": 123,
+                    "paths": {
+                        "directory": "/tmp/i22/data/cm12345-3",
+                        "scanFile": "/tmp/i22/data/cm12345-3/i22-123"
+                    }
+                }));
+                then.status(500);
+            })
+            .await;
+        let working = server
+            .mock_async(|when, then| {
+                when.method("POST")
+                    .path("/events")
+                    .json_body_includes(r#"{"scanNumber": 124}"#);
+                then.status(200);
+            })
+            .await;
+
+        let publisher = ScanPublisher::webhook(server.url("/events").parse().unwrap(), 4);
+        publisher.publish(event(123));
+        publisher.publish(event(124));
+
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while working.calls_async().await == 0 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("Timed out waiting for scan event");
+        failing.assert_async().await;
+        working.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn full_buffer_drops_events() {
+        let server = MockServer::start_async().await;
+        let hook = server
+            .mock_async(|when, then| {
+                when.method("POST").path("/events");
+                then.status(200).delay(Duration::from_millis(50));
+            })
+            .await;
+
+        let publisher = ScanPublisher::webhook(server.url("/events").parse().unwrap(), 1);
+        for num in 0..10 {
+            // Publishing never waits even when the buffer is full
+            publisher.publish(event(num));
+        }
+        drop(publisher);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let calls = hook.calls_async().await;
+        assert!(
+            (1..=2).contains(&calls),
+            "Unexpected number of events sent: {calls}"
+        );
+    }
+}
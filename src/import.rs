@@ -0,0 +1,184 @@
+// Copyright 2024 Diamond Light Source
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use tracing::debug;
+
+use crate::cli::ImportOptions;
+use crate::db_service::{PoolSize, SqliteScanPathService};
+use crate::numtracker::GdaNumTracker;
+
+/// Set the DB scan number for an instrument to the highest number file in an existing tracker
+/// directory.
+///
+/// The DB must already exist and the directory must contain at least one tracker file. The DB
+/// scan number is not lowered unless `force` is set. A summary is printed to stdout. Returns true
+/// if the DB is up to date with the tracker directory.
+pub async fn import_tracker(opts: ImportOptions) -> bool {
+    debug!(?opts, "Importing tracker directory");
+    let ext = opts.extension.as_deref().unwrap_or(&opts.instrument);
+    let high = match GdaNumTracker::high_file(&opts.directory, ext).await {
+        Ok(high) => high,
+        Err(e) => {
+            println!("Could not read tracker directory {:?}: {e}", opts.directory);
+            return false;
+        }
+    };
+    if high == 0 {
+        println!(
+            "No tracker files with extension {ext:?} found in {:?}",
+            opts.directory
+        );
+        return false;
+    }
+    let db = match SqliteScanPathService::open_existing(&opts.db, PoolSize::default()).await {
+        Ok(db) => db,
+        Err(e) => {
+            println!("Could not open DB {:?}: {e}", opts.db);
+            return false;
+        }
+    };
+    let before = match db.current_configuration(&opts.instrument).await {
+        Ok(conf) => conf.scan_number(),
+        Err(e) => {
+            println!("Could not read configuration: {e}");
+            return false;
+        }
+    };
+    if high == before {
+        println!("{}: scan number is already {before}", opts.instrument);
+        return true;
+    }
+    if high < before && !opts.force {
+        println!(
+            "{}: DB scan number ({before}) is higher than the latest tracker file ({high}). \
+            Use --force to lower it.",
+            opts.instrument
+        );
+        return false;
+    }
+    match db.set_scan_number(&opts.instrument, high).await {
+        Ok(conf) => {
+            println!("{}: {before} -> {}", opts.instrument, conf.scan_number());
+            true
+        }
+        Err(e) => {
+            println!("Could not update scan number: {e}");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use tempfile::TempDir;
+
+    use super::import_tracker;
+    use crate::cli::ImportOptions;
+    use crate::db_service::{InstrumentConfigurationUpdate, PoolSize, SqliteScanPathService};
+    use crate::paths::{DetectorTemplate, DirectoryTemplate, PathSpec as _, ScanTemplate};
+
+    /// Create a DB with i22 at the given scan number and a tracker directory whose latest file
+    /// is 122.i22
+    async fn setup(scan_number: u32) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let db = SqliteScanPathService::connect(&dir.path().join("test.db"), PoolSize::default())
+            .await
+            .unwrap();
+        let mut update = InstrumentConfigurationUpdate::empty("i22").with_scan_number(scan_number);
+        update.directory = DirectoryTemplate::new_checked("/tmp/{instrument}/{visit}").ok();
+        update.scan = ScanTemplate::new_checked("{instrument}-{scan_number}").ok();
+        update.detector = DetectorTemplate::new_checked("{scan_number}-{detector}").ok();
+        update.insert_new(&db).await.unwrap();
+        let trackers = dir.path().join("i22");
+        fs::create_dir(&trackers).unwrap();
+        fs::File::create(trackers.join("121.i22")).unwrap();
+        fs::File::create(trackers.join("122.i22")).unwrap();
+        dir
+    }
+
+    fn options(dir: &Path, force: bool) -> ImportOptions {
+        ImportOptions {
+            db: dir.join("test.db"),
+            instrument: "i22".into(),
+            directory: dir.join("i22"),
+            extension: None,
+            force,
+        }
+    }
+
+    async fn db_number(dir: &Path) -> u32 {
+        SqliteScanPathService::connect(&dir.join("test.db"), PoolSize::default())
+            .await
+            .unwrap()
+            .current_configuration("i22")
+            .await
+            .unwrap()
+            .scan_number()
+    }
+
+    #[rstest::rstest]
+    #[case::raised(100, false, true, 122)]
+    #[case::unchanged(122, false, true, 122)]
+    #[case::not_lowered(150, false, false, 150)]
+    #[case::forced(150, true, true, 122)]
+    #[tokio::test]
+    async fn import(
+        #[case] initial: u32,
+        #[case] force: bool,
+        #[case] success: bool,
+        #[case] expected: u32,
+    ) {
+        let dir = setup(initial).await;
+        assert_eq!(import_tracker(options(dir.path(), force)).await, success);
+        assert_eq!(db_number(dir.path()).await, expected);
+    }
+
+    #[tokio::test]
+    async fn missing_instrument() {
+        let dir = setup(100).await;
+        let opts = ImportOptions {
+            instrument: "b21".into(),
+            extension: Some("i22".into()),
+            ..options(dir.path(), true)
+        };
+        assert!(!import_tracker(opts).await);
+        assert_eq!(db_number(dir.path()).await, 100);
+    }
+
+    #[tokio::test]
+    async fn missing_db() {
+        let dir = setup(100).await;
+        let opts = ImportOptions {
+            db: dir.path().join("missing.db"),
+            ..options(dir.path(), false)
+        };
+        assert!(!import_tracker(opts).await);
+        assert!(!dir.path().join("missing.db").exists());
+    }
+
+    #[tokio::test]
+    async fn no_tracker_files() {
+        let dir = setup(100).await;
+        fs::create_dir(dir.path().join("empty")).unwrap();
+        let opts = ImportOptions {
+            directory: dir.path().join("empty"),
+            ..options(dir.path(), true)
+        };
+        assert!(!import_tracker(opts).await);
+        assert_eq!(db_number(dir.path()).await, 100);
+    }
+}
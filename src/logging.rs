@@ -14,7 +14,8 @@
 
 use opentelemetry::trace::TracerProvider as _;
 use opentelemetry::{global, KeyValue};
-use opentelemetry_otlp::{ExporterBuildError, SpanExporter, WithExportConfig as _};
+use opentelemetry_otlp::{ExporterBuildError, MetricExporter, SpanExporter, WithExportConfig as _};
+use opentelemetry_sdk::metrics::SdkMeterProvider;
 use opentelemetry_sdk::trace::SdkTracerProvider;
 use opentelemetry_sdk::Resource;
 use opentelemetry_semantic_conventions::resource::{SERVICE_NAME, SERVICE_VERSION};
@@ -58,6 +59,17 @@ where
     S: Subscriber + for<'s> LookupSpan<'s>,
 {
     if let Some(endpoint) = endpoint {
+        // Metrics are sent to the same platform as traces
+        let meters = SdkMeterProvider::builder()
+            .with_periodic_exporter(
+                MetricExporter::builder()
+                    .with_tonic()
+                    .with_endpoint(endpoint.clone())
+                    .build()?,
+            )
+            .with_resource(resource())
+            .build();
+        global::set_meter_provider(meters);
         let provider = SdkTracerProvider::builder()
             .with_batch_exporter(
                 SpanExporter::builder()
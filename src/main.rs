@@ -16,12 +16,15 @@ use std::error::Error;
 
 use cli::{Cli, Command};
 
+mod audit;
 mod build_info;
+mod check;
 mod cli;
 #[cfg(feature = "client")]
 mod client;
 mod db_service;
 mod graphql;
+mod import;
 mod logging;
 mod numtracker;
 mod paths;
@@ -32,16 +35,36 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let args = Cli::init();
     let _ = logging::init(args.log_level(), args.tracing());
     match args.command {
+        Command::Serve(opts) if opts.print_config() => cli::print_config(),
         Command::Serve(opts) => graphql::serve_graphql(opts).await,
         #[cfg(not(feature = "client"))]
         Command::Client { .. } => {
             println!("Client subcommand requires 'client' feature to be enabled when building")
         }
         #[cfg(feature = "client")]
-        Command::Client(opts) => client::run_client(opts).await,
+        Command::Client(opts) => {
+            if !client::run_client(opts).await {
+                std::process::exit(1);
+            }
+        }
         Command::Schema => {
             graphql::graphql_schema(std::io::stdout()).expect("Failed to write schema")
         }
+        Command::Check(opts) => {
+            if !check::run_checks(opts).await {
+                std::process::exit(1);
+            }
+        }
+        Command::ImportTracker(opts) => {
+            if !import::import_tracker(opts).await {
+                std::process::exit(1);
+            }
+        }
+        Command::Audit(opts) => {
+            if !audit::audit_templates(opts).await {
+                std::process::exit(1);
+            }
+        }
     }
     Ok(())
 }
@@ -12,15 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::HashSet;
-use std::fmt::{Debug, Display};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Display, Write as _};
 use std::hash::Hash;
 
 use derive_more::{Display, Error, From};
+use sha2::{Digest as _, Sha256};
 
-use crate::template::{PathTemplate, PathTemplateError};
+use crate::template::{FieldSource, PathTemplate, PathTemplateError};
 
-#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Display, Clone, PartialEq, Eq, Hash)]
 pub enum DirectoryField {
     #[display("year")]
     Year,
@@ -28,11 +30,22 @@ pub enum DirectoryField {
     Visit,
     #[display("proposal")]
     Proposal,
+    #[display("proposal_code")]
+    ProposalCode,
+    #[display("proposal_number")]
+    ProposalNumber,
     #[display("instrument")]
     Instrument,
+    #[display("facility")]
+    Facility,
+    #[display("visit_hash")]
+    VisitHash,
+    /// A constant configured for the service, referenced as `{custom.<name>}`
+    #[display("custom.{_0}")]
+    Custom(String),
 }
 
-#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Display, Clone, PartialEq, Eq, Hash)]
 pub enum ScanField {
     #[display("subdirectory")]
     Subdirectory,
@@ -42,10 +55,14 @@ pub enum ScanField {
     Directory(DirectoryField),
 }
 
-#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Display, Clone, PartialEq, Eq, Hash)]
 pub enum DetectorField {
     #[display("detector")]
     Detector,
+    /// The subdirectory requested for detector files, or the scan's subdirectory if there isn't
+    /// one
+    #[display("detector_subdirectory")]
+    DetectorSubdirectory,
     #[display("{_0}")]
     Scan(ScanField),
 }
@@ -61,8 +78,53 @@ impl TryFrom<String> for DirectoryField {
             "year" => Ok(DirectoryField::Year),
             "visit" => Ok(DirectoryField::Visit),
             "proposal" => Ok(DirectoryField::Proposal),
+            "proposal_code" => Ok(DirectoryField::ProposalCode),
+            "proposal_number" => Ok(DirectoryField::ProposalNumber),
             "instrument" => Ok(DirectoryField::Instrument),
-            _ => Err(InvalidKey(value)),
+            "facility" => Ok(DirectoryField::Facility),
+            "visit_hash" => Ok(DirectoryField::VisitHash),
+            _ => match value.strip_prefix("custom.") {
+                Some(name) if valid_custom_name(name) => Ok(DirectoryField::Custom(name.into())),
+                _ => Err(InvalidKey(value)),
+            },
+        }
+    }
+}
+
+/// Custom field names are limited to the characters that are valid in other field names
+fn valid_custom_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+impl DirectoryField {
+    /// The name of the configured constant this field refers to, if it is a custom field
+    pub fn custom(&self) -> Option<&str> {
+        match self {
+            DirectoryField::Custom(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl ScanField {
+    /// The name of the configured constant this field refers to, if it is a custom field
+    pub fn custom(&self) -> Option<&str> {
+        match self {
+            ScanField::Directory(field) => field.custom(),
+            _ => None,
+        }
+    }
+}
+
+impl DetectorField {
+    /// The name of the configured constant this field refers to, if it is a custom field
+    pub fn custom(&self) -> Option<&str> {
+        match self {
+            DetectorField::Scan(field) => field.custom(),
+            DetectorField::Detector | DetectorField::DetectorSubdirectory => None,
         }
     }
 }
@@ -83,6 +145,7 @@ impl TryFrom<String> for DetectorField {
     fn try_from(value: String) -> Result<Self, Self::Error> {
         match value.as_str() {
             "detector" => Ok(DetectorField::Detector),
+            "detector_subdirectory" => Ok(DetectorField::DetectorSubdirectory),
             _ => Ok(DetectorField::Scan(ScanField::try_from(value)?)),
         }
     }
@@ -100,16 +163,55 @@ pub trait PathSpec {
             (false, true) => Err(InvalidPathTemplate::ShouldBeRelative),
             _ => Ok(()),
         }?;
-        let fields = template.referenced_fields().collect::<HashSet<_>>();
+        let fields = template.required_fields().collect::<HashSet<_>>();
+        let truncated = template.truncated_fields().collect::<HashSet<_>>();
         for f in Self::REQUIRED {
             if !fields.contains(f) {
                 return Err(InvalidPathTemplate::MissingField(f.to_string()));
             }
+            // Required fields keep paths unique so cutting them short could reuse paths
+            if truncated.contains(f) {
+                return Err(InvalidPathTemplate::TruncatedField(f.to_string()));
+            }
         }
         Ok(template)
     }
 
+    /// Ensure that a template is no longer than the given number of characters, eg to prevent an
+    /// accidentally pasted string being stored as a template. This should be checked before the
+    /// template is parsed.
+    fn check_length(raw: &str, limit: usize) -> Result<(), InvalidPathTemplate> {
+        let length = raw.chars().count();
+        if length > limit {
+            return Err(InvalidPathTemplate::TooLong { length, limit });
+        }
+        Ok(())
+    }
+
+    /// Ensure that every custom field referenced by a template has a value in the given
+    /// constants
+    fn check_custom(
+        template: &PathTemplate<Self::Field>,
+        constants: &HashMap<String, String>,
+    ) -> Result<(), InvalidPathTemplate> {
+        for field in template.referenced_fields() {
+            if let Some(name) = Self::custom_name(field) {
+                if !constants.contains_key(name) {
+                    return Err(InvalidPathTemplate::UnknownCustomField(name.into()));
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn describe() -> &'static str;
+
+    /// All the fields that can be used in a template of this kind. Custom fields are not
+    /// included as they depend on the service's configuration.
+    fn known_fields() -> Vec<Self::Field>;
+
+    /// The name of the constant a field refers to if it is a custom field
+    fn custom_name(field: &Self::Field) -> Option<&str>;
 }
 
 #[derive(Debug, Display, Error, From, PartialEq)]
@@ -123,6 +225,14 @@ pub enum InvalidPathTemplate {
     ShouldBeRelative,
     #[display("Template should reference missing field: {_0:?}")]
     MissingField(#[error(ignore)] String),
+    #[display("Field {_0:?} cannot be truncated as it is needed to keep paths unique")]
+    TruncatedField(#[error(ignore)] String),
+    #[display("Template produces the same path ({_0:?}) for different detectors")]
+    DetectorCollision(#[error(ignore)] String),
+    #[display("Template is too long ({length} characters, limit is {limit})")]
+    TooLong { length: usize, limit: usize },
+    #[display("Template references custom field {_0:?} but it is not configured for this service")]
+    UnknownCustomField(#[error(ignore)] String),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -144,6 +254,23 @@ impl PathSpec for DirectoryTemplate {
             "It should be an absolute path and contain placeholders for {instrument} and {visit}."
         )
     }
+
+    fn known_fields() -> Vec<Self::Field> {
+        vec![
+            DirectoryField::Year,
+            DirectoryField::Visit,
+            DirectoryField::Proposal,
+            DirectoryField::ProposalCode,
+            DirectoryField::ProposalNumber,
+            DirectoryField::Instrument,
+            DirectoryField::Facility,
+            DirectoryField::VisitHash,
+        ]
+    }
+
+    fn custom_name(field: &Self::Field) -> Option<&str> {
+        field.custom()
+    }
 }
 
 impl PathSpec for ScanTemplate {
@@ -158,6 +285,21 @@ impl PathSpec for ScanTemplate {
             "It should be a relative path and contain a placeholder for {scan_number} to ensure files are unique."
         )
     }
+
+    fn known_fields() -> Vec<Self::Field> {
+        [ScanField::Subdirectory, ScanField::ScanNumber]
+            .into_iter()
+            .chain(
+                DirectoryTemplate::known_fields()
+                    .into_iter()
+                    .map(ScanField::Directory),
+            )
+            .collect()
+    }
+
+    fn custom_name(field: &Self::Field) -> Option<&str> {
+        field.custom()
+    }
 }
 
 impl PathSpec for DetectorTemplate {
@@ -179,14 +321,121 @@ impl PathSpec for DetectorTemplate {
             "detectors."
         )
     }
+
+    fn known_fields() -> Vec<Self::Field> {
+        [DetectorField::Detector, DetectorField::DetectorSubdirectory]
+            .into_iter()
+            .chain(
+                ScanTemplate::known_fields()
+                    .into_iter()
+                    .map(DetectorField::Scan),
+            )
+            .collect()
+    }
+
+    fn custom_name(field: &Self::Field) -> Option<&str> {
+        field.custom()
+    }
+}
+
+impl DetectorTemplate {
+    /// Check that a detector template produces different paths for different detectors.
+    ///
+    /// Requiring the {detector} field is not enough on its own as the segment containing it may
+    /// be left out of the rendered path, eg if it also contains an optional field. The template
+    /// is rendered with other fields both empty and populated to catch these cases.
+    pub fn check_unique(template: &PathTemplate<DetectorField>) -> Result<(), InvalidPathTemplate> {
+        for others in ["", "sample"] {
+            let first = template.render(&SampleDetector("first", others));
+            let second = template.render(&SampleDetector("second", others));
+            if first == second {
+                return Err(InvalidPathTemplate::DetectorCollision(
+                    first.to_string_lossy().into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Get the proposal part of a visit, eg `cm12345` for `cm12345-3`. Visits without a `-` are
+/// treated as being entirely proposal.
+pub fn proposal(visit: &str) -> &str {
+    visit
+        .split_once('-')
+        .map_or(visit, |(proposal, _)| proposal)
+}
+
+/// Split the proposal of a visit into its leading non-numeric type code and the rest of the
+/// proposal, eg `("cm", "12345")` for `cm12345-3`.
+pub fn proposal_parts(visit: &str) -> (&str, &str) {
+    let proposal = proposal(visit);
+    proposal.split_at(
+        proposal
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(proposal.len()),
+    )
+}
+
+/// The numeric parts of an instrument session, along with its proposal code
+#[derive(Debug, PartialEq, Eq)]
+pub struct SessionParts<'v> {
+    /// The leading non-numeric part of the proposal, eg `cm`
+    pub code: &'v str,
+    pub proposal: u32,
+    pub session: u16,
+}
+
+/// Split an instrument session of the form `<code><proposal>-<session>` into its parts, eg `cm`,
+/// `12345` and `3` for `cm12345-3`. Returns None if the proposal or session are not numbers.
+///
+/// This is also used to build authorization requests so that clients can see how a session
+/// will be interpreted.
+pub fn parse_session(visit: &str) -> Option<SessionParts<'_>> {
+    let (_, session) = visit.split_once('-')?;
+    let (code, proposal) = proposal_parts(visit);
+    Some(SessionParts {
+        code,
+        proposal: proposal.parse().ok()?,
+        session: session.parse().ok()?,
+    })
+}
+
+/// Get a short hash of a visit that is always the same for the same visit, eg so that data
+/// directories can be spread between a fixed number of parent directories. The hash is the first
+/// `length` hex characters of the SHA-256 digest of the visit.
+pub fn visit_hash(visit: &str, length: usize) -> String {
+    let mut hash = Sha256::digest(visit)
+        .iter()
+        .fold(String::new(), |mut hex, b| {
+            _ = write!(hex, "{b:02x}");
+            hex
+        });
+    hash.truncate(length);
+    hash
+}
+
+/// FieldSource used to compare the paths generated for different detectors in the same scan
+struct SampleDetector<'s>(&'s str, &'s str);
+
+impl FieldSource<DetectorField> for SampleDetector<'_> {
+    fn resolve(&self, field: &DetectorField) -> Cow<'_, str> {
+        match field {
+            DetectorField::Detector => self.0.into(),
+            DetectorField::Scan(ScanField::ScanNumber) => "1".into(),
+            _ => self.1.into(),
+        }
+    }
 }
 
 #[cfg(test)]
 mod paths_tests {
+    use std::collections::HashMap;
     use std::fmt::Debug;
 
     use super::{
-        DetectorTemplate, DirectoryTemplate, InvalidPathTemplate, PathSpec as _, ScanTemplate,
+        parse_session, proposal, proposal_parts, visit_hash, DetectorTemplate, DirectoryTemplate,
+        InvalidPathTemplate, PathSpec, ScanTemplate, SessionParts,
     };
     use crate::template::{ErrorKind, PathTemplateError};
 
@@ -215,12 +464,53 @@ mod paths_tests {
         }
     }
 
+    /// Every known field should be accepted when parsing a template
+    fn known_fields_round_trip<S: PathSpec>()
+    where
+        <S::Field as TryFrom<String>>::Error: Debug,
+        S::Field: Debug,
+    {
+        for field in S::known_fields() {
+            let name = field.to_string();
+            assert_eq!(S::Field::try_from(name).unwrap(), field);
+        }
+    }
+
+    #[test]
+    fn known_fields() {
+        known_fields_round_trip::<DirectoryTemplate>();
+        known_fields_round_trip::<ScanTemplate>();
+        known_fields_round_trip::<DetectorTemplate>();
+        assert_eq!(
+            DetectorTemplate::known_fields()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            [
+                "detector",
+                "detector_subdirectory",
+                "subdirectory",
+                "scan_number",
+                "year",
+                "visit",
+                "proposal",
+                "proposal_code",
+                "proposal_number",
+                "instrument",
+                "facility",
+                "visit_hash"
+            ]
+        );
+    }
+
     #[rstest::rstest]
     #[case::relative("relative/visit/path", InvalidPathTemplate::ShouldBeAbsolute)]
     #[case::invalid_path_incomplete("/data/{unclosed", TemplateErrorType::Incomplete)]
     #[case::invalid_path_empty("/data/{}", TemplateErrorType::Empty)]
     #[case::invalid_path_nested("/data/{nes{ted}}", TemplateErrorType::Nested)]
     #[case::invalid_path_unrecognised("/data/{scan_number}", TemplateErrorType::Unrecognised)]
+    #[case::invalid_custom_name("/data/{custom.Group}", TemplateErrorType::Unrecognised)]
+    #[case::empty_custom_name("/data/{custom.}", TemplateErrorType::Unrecognised)]
     fn invalid_directory<E: PartialEq<InvalidPathTemplate> + Debug>(
         #[case] template: &str,
         #[case] err: E,
@@ -232,10 +522,12 @@ mod paths_tests {
     #[rstest::rstest]
     #[case::absolute("/absolute/scan/path", InvalidPathTemplate::ShouldBeRelative)]
     #[case::missing_scan_number("no_scan_number", InvalidPathTemplate::MissingField("scan_number".into()))]
+    #[case::optional_scan_number("{?scan_number}", InvalidPathTemplate::MissingField("scan_number".into()))]
     #[case::invalid_path_incomplete("data/{unclosed", TemplateErrorType::Incomplete)]
     #[case::invalid_path_empty("data/{}", TemplateErrorType::Empty)]
     #[case::invalid_path_nested("data/{nes{ted}}", TemplateErrorType::Nested)]
     #[case::invalid_path_unrecognised("data/{detector}", TemplateErrorType::Unrecognised)]
+    #[case::truncated_scan_number("{scan_number!trunc:2}", InvalidPathTemplate::TruncatedField("scan_number".into()))]
     fn invalid_scan<E: PartialEq<InvalidPathTemplate> + Debug>(
         #[case] template: &str,
         #[case] err: E,
@@ -252,6 +544,8 @@ mod paths_tests {
     #[case::invalid_path_empty("data/{}", TemplateErrorType::Empty)]
     #[case::invalid_path_nested("data/{nes{ted}}", TemplateErrorType::Nested)]
     #[case::invalid_path_unrecognised("data/{unknown}", TemplateErrorType::Unrecognised)]
+    #[case::truncated_detector("{scan_number}-{detector!trunc:3}", InvalidPathTemplate::TruncatedField("detector".into()))]
+    #[case::truncated_scan_number("{scan_number!trunc:4:04}-{detector}", InvalidPathTemplate::TruncatedField("scan_number".into()))]
     fn invalid_detector<E: PartialEq<InvalidPathTemplate> + Debug>(
         #[case] template: &str,
         #[case] err: E,
@@ -259,4 +553,132 @@ mod paths_tests {
         let e = DetectorTemplate::new_checked(template).unwrap_err();
         assert_eq!(err, e);
     }
+
+    #[rstest::rstest]
+    #[case::directory(DirectoryTemplate, "/data/{instrument}/{visit}")]
+    #[case::scan(ScanTemplate, "{subdirectory}/{scan_number}")]
+    #[case::detector(DetectorTemplate, "{scan_number}-{detector}")]
+    fn template_length<S: PathSpec>(#[case] _spec: S, #[case] template: &str) {
+        let length = template.len();
+        S::check_length(template, length + 1).unwrap();
+        S::check_length(template, length).unwrap();
+        assert_eq!(
+            S::check_length(template, length - 1).unwrap_err(),
+            InvalidPathTemplate::TooLong {
+                length,
+                limit: length - 1
+            }
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::at_limit(1024, Ok(()))]
+    #[case::above_limit(1025, Err(InvalidPathTemplate::TooLong { length: 1025, limit: 1024 }))]
+    fn invalid_template_length(
+        #[case] length: usize,
+        #[case] expected: Result<(), InvalidPathTemplate>,
+    ) {
+        // The raw text is checked so an over-long template is rejected without being parsed
+        let raw = "{".repeat(length);
+        assert_eq!(ScanTemplate::check_length(&raw, 1024), expected);
+    }
+
+    fn constants() -> HashMap<String, String> {
+        [("group".to_string(), "soft_condensed".to_string())].into()
+    }
+
+    #[rstest::rstest]
+    #[case::directory(DirectoryTemplate, "/data/{custom.group}/{visit}")]
+    #[case::scan(ScanTemplate, "{custom.group}/{scan_number}")]
+    #[case::detector(DetectorTemplate, "{scan_number}/{custom.group}-{detector}")]
+    fn configured_custom_field<S: PathSpec>(#[case] _spec: S, #[case] template: &str) {
+        let template = S::new_checked(template).unwrap();
+        S::check_custom(&template, &constants()).unwrap();
+        // The field is written the same way it is parsed
+        assert!(template.to_string().contains("{custom.group}"));
+    }
+
+    #[rstest::rstest]
+    #[case::directory(DirectoryTemplate, "/data/{custom.cohort}/{visit}")]
+    #[case::optional(DirectoryTemplate, "/data/{?custom.cohort}/{visit}")]
+    #[case::scan(ScanTemplate, "{custom.cohort}/{scan_number}")]
+    #[case::detector(DetectorTemplate, "{scan_number}/{custom.cohort}-{detector}")]
+    fn unconfigured_custom_field<S: PathSpec>(#[case] _spec: S, #[case] template: &str) {
+        let template = S::new_checked(template).unwrap();
+        assert_eq!(
+            S::check_custom(&template, &constants()).unwrap_err(),
+            InvalidPathTemplate::UnknownCustomField("cohort".into())
+        );
+        S::check_custom(&template, &[("cohort".to_string(), "a".to_string())].into()).unwrap();
+    }
+
+    #[rstest::rstest]
+    #[case::separate_segment("{scan_number}/{detector}")]
+    #[case::shared_segment("{subdirectory}/{instrument}-{scan_number}-{detector}")]
+    #[case::optional_segment("{scan_number}/{?subdirectory}/{detector}")]
+    #[case::truncated_other_field("{scan_number}/{visit!trunc:4}-{detector}")]
+    fn unique_detector_paths(#[case] template: &str) {
+        let template = DetectorTemplate::new_checked(template).unwrap();
+        DetectorTemplate::check_unique(&template).unwrap();
+    }
+
+    /// Templates that include the required fields but can still omit the detector from the path
+    #[rstest::rstest]
+    #[case::optional_prefix("{scan_number}/{?subdirectory}_{detector}")]
+    #[case::optional_suffix("{scan_number}/{detector}-{?visit}")]
+    fn colliding_detector_paths(#[case] template: &str) {
+        let template = DetectorTemplate::new_checked(template).unwrap();
+        assert_eq!(
+            DetectorTemplate::check_unique(&template).unwrap_err(),
+            InvalidPathTemplate::DetectorCollision("1".into())
+        );
+    }
+
+    #[rstest::rstest]
+    #[case::visit("cm12345-3", "cm12345", "cm", "12345")]
+    #[case::other_code("mg98765-12", "mg98765", "mg", "98765")]
+    #[case::no_session("cm12345", "cm12345", "cm", "12345")]
+    #[case::no_code("12345-3", "12345", "", "12345")]
+    #[case::no_number("commissioning-3", "commissioning", "commissioning", "")]
+    #[case::mixed("ab1c2-3", "ab1c2", "ab", "1c2")]
+    fn proposals(
+        #[case] visit: &str,
+        #[case] expected: &str,
+        #[case] code: &str,
+        #[case] number: &str,
+    ) {
+        assert_eq!(proposal(visit), expected);
+        assert_eq!(proposal_parts(visit), (code, number));
+    }
+
+    #[rstest::rstest]
+    #[case::visit("cm12345-3", Some(("cm", 12345, 3)))]
+    #[case::no_code("12345-3", Some(("", 12345, 3)))]
+    #[case::no_proposal("cm-3", None)]
+    #[case::no_session("cm12345", None)]
+    #[case::invalid_session("cm12345-abc", None)]
+    #[case::invalid_proposal("cm123abc-12", None)]
+    #[case::negative_session("cm1234--12", None)]
+    #[case::session_too_large("cm12345-65536", None)]
+    fn sessions(#[case] visit: &str, #[case] expected: Option<(&str, u32, u16)>) {
+        let expected = expected.map(|(code, proposal, session)| SessionParts {
+            code,
+            proposal,
+            session,
+        });
+        assert_eq!(parse_session(visit), expected);
+    }
+
+    #[rstest::rstest]
+    #[case::default("cm12345-3", 2, "98")]
+    #[case::longer("cm12345-3", 6, "985fd8")]
+    #[case::other_visit("cm12345-4", 2, "13")]
+    #[case::full_hash("cm12345-3", 100, "985fd844")]
+    fn visit_hashes(#[case] visit: &str, #[case] length: usize, #[case] expected: &str) {
+        let hash = visit_hash(visit, length);
+        assert!(hash.starts_with(expected), "{hash} != {expected}");
+        assert_eq!(hash.len(), length.min(64));
+        // The same visit always maps to the same shard
+        assert_eq!(hash, visit_hash(visit, length));
+    }
 }
@@ -14,7 +14,7 @@
 
 use std::borrow::Cow;
 use std::fmt::{Debug, Display};
-use std::path::{Component, PathBuf};
+use std::path::PathBuf;
 
 use derive_more::{Display, Error};
 
@@ -26,15 +26,136 @@ pub trait FieldSource<F> {
 enum Part<Field> {
     Literal(String),
     Field(Field),
+    Formatted(Field, FormatSpec),
+    /// A field marked with a leading '?', eg `{?roi}`. If it resolves to an empty value, the
+    /// path segment containing it is omitted entirely.
+    Optional(Field, Option<FormatSpec>),
 }
 
 impl<Field> Part<Field> {
     fn field(&self) -> Option<&Field> {
         match self {
             Part::Literal(_) => None,
-            Part::Field(f) => Some(f),
+            Part::Field(f) | Part::Formatted(f, _) | Part::Optional(f, _) => Some(f),
         }
     }
+
+    /// Resolve this part using the given source. Returns None if this is an optional field that
+    /// resolved to an empty value.
+    fn resolve<'s, Src: FieldSource<Field>>(&'s self, src: &'s Src) -> Option<Cow<'s, str>> {
+        match self {
+            Part::Literal(text) => Some(text.as_str().into()),
+            Part::Field(f) => Some(src.resolve(f)),
+            Part::Formatted(f, spec) => Some(spec.apply(src.resolve(f))),
+            Part::Optional(f, spec) => match src.resolve(f) {
+                value if value.is_empty() => None,
+                value => Some(match spec {
+                    Some(spec) => spec.apply(value),
+                    None => value,
+                }),
+            },
+        }
+    }
+}
+
+/// The format applied to a field's value when it is rendered, eg `{scan_number:05}`.
+///
+/// A value can be truncated to a maximum number of characters with a `!trunc:` modifier after
+/// the field name, eg `{visit!trunc:8}`, and zero padded to a minimum width with a '0' followed
+/// by the width after a ':'. Both can be combined, eg `{scan_number!trunc:6:04}`, in which case
+/// the value is truncated before it is padded.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct FormatSpec {
+    /// The maximum number of characters to keep from the value
+    truncate: Option<usize>,
+    /// The minimum width to pad the value to. A width of 0 means no padding.
+    width: usize,
+}
+
+impl FormatSpec {
+    /// Split a placeholder into its field name and format spec. Returns None if the placeholder
+    /// includes a format spec that is not valid.
+    fn split(placeholder: &str) -> Option<(&str, Option<Self>)> {
+        let (key, truncate, padding) = match placeholder.split_once('!') {
+            // Modifiers must come before any padding
+            Some((key, _)) if key.contains(':') => return None,
+            Some((key, modifier)) => {
+                let modifier = modifier.strip_prefix("trunc:")?;
+                let (length, padding) = match modifier.split_once(':') {
+                    Some((length, padding)) => (length, Some(padding)),
+                    None => (modifier, None),
+                };
+                (key, Some(digits(length).filter(|&len| len > 0)?), padding)
+            }
+            None => match placeholder.split_once(':') {
+                Some((key, padding)) => (key, None, Some(padding)),
+                None => return Some((placeholder, None)),
+            },
+        };
+        let width = match padding {
+            Some(padding) => {
+                digits(padding.strip_prefix('0')?).filter(|&width| width <= MAX_FORMAT_WIDTH)?
+            }
+            None => 0,
+        };
+        Some((key, Some(Self { truncate, width })))
+    }
+
+    fn apply<'v>(&self, value: Cow<'v, str>) -> Cow<'v, str> {
+        // Cut at a char boundary so that multi-byte characters are never split
+        let value = match self.truncate.and_then(|len| value.char_indices().nth(len)) {
+            Some((end, _)) => value[..end].to_owned().into(),
+            None => value,
+        };
+        match value.chars().count() {
+            len if len >= self.width => value,
+            len => format!("{}{value}", "0".repeat(self.width - len)).into(),
+        }
+    }
+
+    /// Best effort reversal of [Self::apply], assuming that any leading zeros in text of exactly
+    /// the padded width were added as padding. Returns None if the text could not have been
+    /// formatted with this spec. Truncated values cannot be recovered so the text is returned as
+    /// it is.
+    fn unapply<'v>(&self, text: &'v str) -> Option<&'v str> {
+        let value = match text.chars().count() {
+            len if len < self.width => None,
+            len if len > self.width => Some(text),
+            _ => match text.trim_start_matches('0') {
+                "" => Some(&text[text.len().saturating_sub(1)..]),
+                trimmed => Some(trimmed),
+            },
+        }?;
+        match self.truncate {
+            Some(len) if value.chars().count() > len => None,
+            _ => Some(value),
+        }
+    }
+}
+
+/// The largest padding width accepted in a format spec. This is more than enough for any number
+/// that fits in a u64 and stops templates from rendering arbitrarily large paths. Truncation
+/// lengths are not limited as they can only make values shorter.
+const MAX_FORMAT_WIDTH: usize = 20;
+
+/// Parse a number made up only of ASCII digits (no sign or whitespace)
+fn digits(text: &str) -> Option<usize> {
+    if !text.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+impl Display for FormatSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(len) = self.truncate {
+            write!(f, "!trunc:{len}")?;
+        }
+        if self.width > 0 {
+            write!(f, ":0{}", self.width)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -48,6 +169,9 @@ impl<F: Display> Display for Template<F> {
             match p {
                 Part::Literal(lit) => f.write_str(lit.as_str())?,
                 Part::Field(fld) => write!(f, "{{{fld}}}")?,
+                Part::Formatted(fld, spec) => write!(f, "{{{fld}{spec}}}")?,
+                Part::Optional(fld, None) => write!(f, "{{?{fld}}}")?,
+                Part::Optional(fld, Some(spec)) => write!(f, "{{?{fld}{spec}}}")?,
             }
         }
         Ok(())
@@ -83,10 +207,10 @@ enum PathType {
     Relative,
 }
 impl PathType {
-    fn init(self) -> PathBuf {
+    fn init(self) -> String {
         match self {
-            PathType::Absolute => PathBuf::from("/"),
-            PathType::Relative => PathBuf::new(),
+            PathType::Absolute => "/".into(),
+            PathType::Relative => String::new(),
         }
     }
 }
@@ -99,11 +223,17 @@ pub enum PathTemplateError {
     TemplateError(TemplateError),
 }
 
+/// The states of the template parser
+///
+/// Braces are the only special characters in literal text. A ':' or '!' is only special within a
+/// placeholder (the `PartialKey` state) where it separates the field name from its format spec,
+/// so literal text such as `12:00` is left untouched. Similarly, a '?' is only special as the
+/// first character of a placeholder where it marks the field as optional.
 #[derive(Debug)]
 enum ParseState {
     /// We haven't started parsing anything yet
     Init,
-    /// We are parsing a field key
+    /// We are parsing a field key, including its format spec if it has one
     PartialKey(String),
     /// We are parsing a literal section of the template
     Literal(String),
@@ -134,6 +264,9 @@ pub enum ErrorKind {
     /// The placeholder was not a recognised key
     #[display("Invalid placeholder")]
     Unrecognised,
+    /// The format spec of a placeholder (the part after the ':' or '!') was not valid
+    #[display("Invalid format spec")]
+    InvalidFormat,
 }
 
 impl TemplateError {
@@ -152,6 +285,9 @@ impl TemplateError {
     fn unknown(position: usize) -> Self {
         Self::new(position, ErrorKind::Unrecognised)
     }
+    fn invalid_format(position: usize) -> Self {
+        Self::new(position, ErrorKind::InvalidFormat)
+    }
     #[cfg(test)]
     pub fn kind(&self) -> ErrorKind {
         self.kind
@@ -182,11 +318,25 @@ impl<F: TryFrom<String>> Template<F> {
                         return Err(TemplateError::empty(i))
                     }
                     ParseState::PartialKey(key) => {
-                        match F::try_from(key) {
-                            Ok(field) => parts.push(Part::Field(field)),
-                            Err(_) => return Err(TemplateError::unknown(i)),
+                        let (optional, key) = match key.strip_prefix('?') {
+                            Some(key) => (true, key.to_string()),
+                            None => (false, key),
+                        };
+                        let Some((key, spec)) = FormatSpec::split(&key) else {
+                            return Err(TemplateError::invalid_format(i));
+                        };
+                        let key = key.to_string();
+                        if key.trim().is_empty() {
+                            return Err(TemplateError::empty(i));
                         }
-                        // parts.push(Part::Field(F::try_from(key)));
+                        let Ok(field) = F::try_from(key) else {
+                            return Err(TemplateError::unknown(i));
+                        };
+                        parts.push(match (optional, spec) {
+                            (true, spec) => Part::Optional(field, spec),
+                            (false, Some(spec)) => Part::Formatted(field, spec),
+                            (false, None) => Part::Field(field),
+                        });
                         state = ParseState::Init;
                     }
                     ParseState::PendingLiteral(_) => return Err(TemplateError::empty(i)),
@@ -221,37 +371,63 @@ impl<F: TryFrom<String>> Template<F> {
 }
 
 impl<F> Template<F> {
+    #[cfg(test)]
     pub fn render<Src: FieldSource<F>>(&self, src: &Src) -> String {
         let mut buf = String::new();
         for part in &self.parts {
-            match part {
-                Part::Literal(text) => buf.push_str(text),
-                Part::Field(f) => buf.push_str(&src.resolve(f)),
-            }
+            buf.push_str(&part.resolve(src).unwrap_or_default());
         }
         buf
     }
+
+    /// Render this template as a single path segment. Returns None if any optional field in the
+    /// template resolved to an empty value, meaning the segment should be omitted.
+    fn render_segment<Src: FieldSource<F>>(&self, src: &Src) -> Option<String> {
+        let mut buf = String::new();
+        for part in &self.parts {
+            buf.push_str(&part.resolve(src)?);
+        }
+        Some(buf)
+    }
+
+    /// Iterate through all the fields in this template that are not marked as optional
+    pub fn required_fields(&self) -> impl Iterator<Item = &F> {
+        self.parts.iter().filter_map(|p| match p {
+            Part::Optional(..) => None,
+            p => p.field(),
+        })
+    }
     /// Iterate through all the fields in this template. Fields may be duplicated if they are
     /// referenced multiple times.
     pub fn referenced_fields(&self) -> impl Iterator<Item = &F> {
         self.parts.iter().filter_map(|p| p.field())
     }
+    /// Iterate through all the fields in this template that are truncated when rendered
+    pub fn truncated_fields(&self) -> impl Iterator<Item = &F> {
+        self.parts.iter().filter_map(|p| match p {
+            Part::Formatted(f, spec) | Part::Optional(f, Some(spec)) if spec.truncate.is_some() => {
+                Some(f)
+            }
+            _ => None,
+        })
+    }
 }
 
 impl<F: TryFrom<String>> PathTemplate<F> {
+    /// Parse a path template, always using '/' as the separator so that templates are handled
+    /// the same way regardless of the platform the service is running on.
     pub(super) fn new<S: AsRef<str>>(template: S) -> Result<Self, PathTemplateError> {
-        let path = PathBuf::from(template.as_ref());
+        let template = template.as_ref();
+        let kind = match template.starts_with('/') {
+            true => PathType::Absolute,
+            false => PathType::Relative,
+        };
         let mut parts = Vec::new();
-        let mut kind = PathType::Relative;
-        for comp in path.components() {
-            match comp {
-                Component::Normal(seg) => match seg.to_str() {
-                    Some(seg) => parts.push(Template::new(seg)?),
-                    None => return Err(PathTemplateError::InvalidPath),
-                },
-                Component::RootDir => kind = PathType::Absolute,
-                Component::CurDir => continue,
-                _ => return Err(PathTemplateError::InvalidPath),
+        for seg in template.split('/') {
+            match seg {
+                "" | "." => continue,
+                ".." => return Err(PathTemplateError::InvalidPath),
+                seg => parts.push(Template::new(seg)?),
             }
         }
         Ok(Self { parts, kind })
@@ -259,15 +435,26 @@ impl<F: TryFrom<String>> PathTemplate<F> {
 }
 
 impl<F> PathTemplate<F> {
+    /// Render this template into a path. Segments that render to empty strings are skipped, as
+    /// are segments containing optional fields that resolve to empty values. Separators in
+    /// rendered fields are normalised so that the path never contains repeated separators.
     pub fn render<Src>(&self, src: &Src) -> PathBuf
     where
         Src: FieldSource<F>,
     {
         let mut path = self.kind.init();
         for part in &self.parts {
-            path.push(part.render(src));
+            let Some(seg) = part.render_segment(src) else {
+                continue;
+            };
+            for component in seg.split('/').filter(|c| !c.is_empty()) {
+                if !(path.is_empty() || path.ends_with('/')) {
+                    path.push('/');
+                }
+                path.push_str(component);
+            }
         }
-        path
+        path.into()
     }
 
     pub fn is_absolute(&self) -> bool {
@@ -279,6 +466,201 @@ impl<F> PathTemplate<F> {
     pub fn referenced_fields(&self) -> impl Iterator<Item = &F> {
         self.parts.iter().flat_map(Template::referenced_fields)
     }
+
+    /// Iterate through all the fields in this path that are not marked as optional. These are the
+    /// fields that will always be present in a rendered path.
+    pub fn required_fields(&self) -> impl Iterator<Item = &F> {
+        self.parts.iter().flat_map(Template::required_fields)
+    }
+
+    /// Iterate through all the fields in this path that are truncated when rendered
+    pub fn truncated_fields(&self) -> impl Iterator<Item = &F> {
+        self.parts.iter().flat_map(Template::truncated_fields)
+    }
+}
+
+/// The values given to each field to produce a path when matching it against a template
+pub type FieldValues<'t, F> = Vec<(&'t F, String)>;
+
+/// The maximum number of segments and field values tried while matching a path against a
+/// template.
+/// Templates with several adjacent fields can be matched in a huge number of ways so the search
+/// is abandoned once this is reached and any matches already found are returned.
+const MAX_MATCH_STEPS: usize = 100_000;
+
+impl<F: PartialEq> PathTemplate<F> {
+    /// Find the field values that would render this template as the given path, stopping once
+    /// `limit` matches have been found.
+    ///
+    /// This is best effort. Templates where fields are not separated by literal text can match in
+    /// several ways so every candidate is returned. Values are only accepted if `accept` returns
+    /// true for them, which can be used to reject values a field could never have, eg a
+    /// non-numeric scan number. A field referenced more than once must have the same value
+    /// everywhere. The search is also abandoned after a fixed number of steps so that
+    /// pathological templates or paths can't take an unbounded amount of time.
+    pub fn matches<'t>(
+        &'t self,
+        path: &str,
+        accept: impl Fn(&F, &str) -> bool,
+        limit: usize,
+    ) -> Vec<FieldValues<'t, F>> {
+        let start = match (self.kind, path.strip_prefix('/')) {
+            (PathType::Absolute, Some(_)) => 1,
+            (PathType::Relative, None) => 0,
+            _ => return vec![],
+        };
+        let mut matcher = Matcher {
+            template: self,
+            path,
+            accept,
+            limit,
+            steps: MAX_MATCH_STEPS,
+            found: vec![],
+        };
+        matcher.segment(0, start, &mut vec![]);
+        matcher.found
+    }
+}
+
+/// Backtracking search through the ways a path could be rendered from a template
+struct Matcher<'t, 'p, F, A> {
+    template: &'t PathTemplate<F>,
+    path: &'p str,
+    accept: A,
+    limit: usize,
+    /// The number of steps remaining before the search is abandoned
+    steps: usize,
+    found: Vec<FieldValues<'t, F>>,
+}
+
+impl<'t, F: PartialEq, A: Fn(&F, &str) -> bool> Matcher<'t, '_, F, A> {
+    /// Match the remaining segments of the template against the path from `pos`
+    fn segment(&mut self, seg: usize, pos: usize, bound: &mut FieldValues<'t, F>) {
+        if self.done() {
+            return;
+        }
+        self.steps -= 1;
+        let Some(template) = self.template.parts.get(seg) else {
+            if pos == self.path.len() && !self.found.contains(bound) {
+                self.found.push(bound.clone());
+            }
+            return;
+        };
+        // The segment is left out if any optional field is empty...
+        let optional = template.parts.iter().filter_map(|p| match p {
+            Part::Optional(f, _) => Some(f),
+            _ => None,
+        });
+        for field in optional {
+            self.with_binding(bound, field, "", |m, bound| m.segment(seg + 1, pos, bound));
+        }
+        // ...or if it renders as an empty string
+        if template.parts.iter().all(|p| p.field().is_some()) {
+            let n = bound.len();
+            if template
+                .parts
+                .iter()
+                .all(|p| self.bind(bound, p.field(), ""))
+            {
+                self.segment(seg + 1, pos, bound);
+            }
+            bound.truncate(n);
+        }
+        let rendered = &self.path[..pos];
+        let start = match rendered.is_empty() || rendered.ends_with('/') {
+            true => pos,
+            false if self.path[pos..].starts_with('/') => pos + 1,
+            false => return,
+        };
+        self.part(seg, 0, start, start, bound);
+    }
+
+    /// Match the remaining parts of a segment against the path from `pos`
+    fn part(
+        &mut self,
+        seg: usize,
+        idx: usize,
+        start: usize,
+        pos: usize,
+        bound: &mut FieldValues<'t, F>,
+    ) {
+        let template = self.template;
+        let Some(part) = template.parts[seg].parts.get(idx) else {
+            // Empty segments are skipped when rendering so they can't be matched here
+            if pos > start {
+                self.segment(seg + 1, pos, bound);
+            }
+            return;
+        };
+        let (field, spec, optional) = match part {
+            Part::Literal(lit) => {
+                if self.path[pos..].starts_with(lit.as_str()) {
+                    self.part(seg, idx + 1, start, pos + lit.len(), bound);
+                }
+                return;
+            }
+            Part::Field(f) => (f, None, false),
+            Part::Formatted(f, spec) => (f, Some(spec), false),
+            Part::Optional(f, spec) => (f, spec.as_ref(), true),
+        };
+        for end in (pos..=self.path.len()).filter(|&e| self.path.is_char_boundary(e)) {
+            if self.done() {
+                return;
+            }
+            self.steps -= 1;
+            let text = &self.path[pos..end];
+            // Empty optional fields remove the segment so are handled separately. Separators at
+            // either end of a value are merged with the path's own separators when rendering so
+            // the value could never be told apart from the one without them.
+            if (optional && text.is_empty()) || text.starts_with('/') || text.ends_with('/') {
+                continue;
+            }
+            let Some(value) = spec.map_or(Some(text), |s| s.unapply(text)) else {
+                continue;
+            };
+            self.with_binding(bound, field, value, |m, bound| {
+                m.part(seg, idx + 1, start, end, bound)
+            });
+        }
+    }
+
+    /// Whether the search should stop, either because enough matches have been found or because
+    /// it has run out of steps
+    fn done(&self) -> bool {
+        self.found.len() >= self.limit || self.steps == 0
+    }
+
+    /// Continue matching with the given value for a field if it is consistent with the values
+    /// found so far
+    fn with_binding(
+        &mut self,
+        bound: &mut FieldValues<'t, F>,
+        field: &'t F,
+        value: &str,
+        next: impl FnOnce(&mut Self, &mut FieldValues<'t, F>),
+    ) {
+        let n = bound.len();
+        if self.bind(bound, Some(field), value) {
+            next(self, bound);
+        }
+        bound.truncate(n);
+    }
+
+    /// Record the value of a field, returning false if it conflicts with an existing value or is
+    /// not accepted
+    fn bind(&self, bound: &mut FieldValues<'t, F>, field: Option<&'t F>, value: &str) -> bool {
+        let Some(field) = field else {
+            return true;
+        };
+        match bound.iter().find(|(f, _)| *f == field) {
+            Some((_, existing)) => existing == value,
+            None if (self.accept)(field, value) => {
+                bound.push((field, value.into()));
+                true
+            }
+            None => false,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -390,6 +772,134 @@ mod parser_tests {
         assert_eq!(temp, error!(7, Nested))
     }
 
+    #[test]
+    fn formatted_field() {
+        let temp = StrTemplate::new("literal:text/{scan_number:05}").unwrap();
+        assert_eq!(
+            temp.parts,
+            vec![
+                literal("literal:text/"),
+                Formatted(
+                    "scan_number".into(),
+                    FormatSpec {
+                        truncate: None,
+                        width: 5
+                    }
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn optional_fields() {
+        let temp = StrTemplate::new("{?roi}_{?scan_number:05}").unwrap();
+        assert_eq!(
+            temp.parts,
+            vec![
+                Optional("roi".into(), None),
+                literal("_"),
+                Optional(
+                    "scan_number".into(),
+                    Some(FormatSpec {
+                        truncate: None,
+                        width: 5
+                    })
+                )
+            ]
+        );
+
+        let temp = StrTemplate::new("{?}").unwrap_err();
+        assert_eq!(temp, error!(2, Empty));
+    }
+
+    #[test]
+    fn truncated_fields() {
+        let temp = StrTemplate::new("{visit!trunc:8}/{?num!trunc:6:04}").unwrap();
+        assert_eq!(
+            temp.parts,
+            vec![
+                Formatted(
+                    "visit".into(),
+                    FormatSpec {
+                        truncate: Some(8),
+                        width: 0
+                    }
+                ),
+                literal("/"),
+                Optional(
+                    "num".into(),
+                    Some(FormatSpec {
+                        truncate: Some(6),
+                        width: 4
+                    })
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn literal_colons() {
+        let temp = StrTemplate::new("12:00:{visit}:").unwrap();
+        assert_eq!(
+            temp.parts,
+            vec![literal("12:00:"), field("visit"), literal(":")]
+        );
+    }
+
+    #[test]
+    fn invalid_format() {
+        let temp = StrTemplate::new("{scan_number:}").unwrap_err();
+        assert_eq!(temp, error!(13, InvalidFormat));
+
+        let temp = StrTemplate::new("{scan_number:5}").unwrap_err();
+        assert_eq!(temp, error!(14, InvalidFormat));
+
+        let temp = StrTemplate::new("{scan_number:05:05}").unwrap_err();
+        assert_eq!(temp, error!(18, InvalidFormat));
+
+        let temp = StrTemplate::new("{:05}").unwrap_err();
+        assert_eq!(temp, error!(4, Empty));
+
+        let temp = StrTemplate::new("{visit!trunc:0}").unwrap_err();
+        assert_eq!(temp, error!(14, InvalidFormat));
+
+        let temp = StrTemplate::new("{visit!trunc:+8}").unwrap_err();
+        assert_eq!(temp, error!(15, InvalidFormat));
+
+        let temp = StrTemplate::new("{visit!trunc}").unwrap_err();
+        assert_eq!(temp, error!(12, InvalidFormat));
+
+        let temp = StrTemplate::new("{visit!upper:8}").unwrap_err();
+        assert_eq!(temp, error!(14, InvalidFormat));
+
+        let temp = StrTemplate::new("{visit:05!trunc:8}").unwrap_err();
+        assert_eq!(temp, error!(17, InvalidFormat));
+    }
+
+    #[test]
+    fn oversized_format() {
+        let temp = StrTemplate::new("{scan_number:020}").unwrap();
+        assert_eq!(temp.to_string(), "{scan_number:020}");
+
+        let temp = StrTemplate::new("{scan_number:021}").unwrap_err();
+        assert_eq!(temp, error!(16, InvalidFormat));
+
+        let temp = StrTemplate::new("{scan_number:0999999999999}").unwrap_err();
+        assert_eq!(temp, error!(26, InvalidFormat));
+
+        let temp = StrTemplate::new("{scan_number:099999999999999999999999}").unwrap_err();
+        assert_eq!(temp, error!(37, InvalidFormat));
+
+        // Truncating can only make values shorter so long lengths are allowed
+        let temp = StrTemplate::new("{visit!trunc:21}").unwrap();
+        assert_eq!(temp.to_string(), "{visit!trunc:21}");
+        let temp = StrTemplate::new("{visit!trunc:1000:020}").unwrap();
+        assert_eq!(temp.to_string(), "{visit!trunc:1000:020}");
+
+        let temp = StrTemplate::new("{visit!trunc:8:021}").unwrap_err();
+        assert_eq!(temp, error!(18, InvalidFormat));
+    }
+
     #[test]
     fn incomplete_key() {
         let temp = StrTemplate::new("incomplete {key").unwrap_err();
@@ -513,7 +1023,121 @@ mod path_template_tests {
         assert_eq!(
             PathTemplate::<String>::new("../parent/directory").unwrap_err(),
             PathTemplateError::InvalidPath
-        )
+        );
+        assert_eq!(
+            PathTemplate::<String>::new("/nested/../parent").unwrap_err(),
+            PathTemplateError::InvalidPath
+        );
+    }
+
+    /// Paths should be split on '/' only so that the output is the same on every platform
+    #[rstest::rstest]
+    #[case::drive_letter("C:/data/{visit}", "C:/data/VISIT")]
+    #[case::backslash(r"data\{visit}/scan", r"data\VISIT/scan")]
+    #[case::repeated_separator("//data//{visit}", "/data/VISIT")]
+    #[case::trailing_separator("data/{visit}/", "data/VISIT")]
+    fn platform_independent(#[case] template: &'static str, #[case] expected: &str) {
+        let path = from_template(template, &EchoSource);
+        assert_eq!(path.to_str().unwrap(), expected);
+    }
+
+    #[test]
+    fn absolute_field_appended() {
+        // A field rendering to an absolute path should not replace the preceding path
+        struct RootSource;
+        impl FieldSource<String> for RootSource {
+            fn resolve(&self, _: &String) -> Cow<'_, str> {
+                "/root".into()
+            }
+        }
+        let path = from_template("/data/{field}/end", &RootSource);
+        assert_eq!(path.to_str().unwrap(), "/data/root/end");
+        let path = from_template("data/{field}/", &RootSource);
+        assert_eq!(path.to_str().unwrap(), "data/root");
+    }
+
+    #[test]
+    fn formatted_fields() {
+        struct NumSource;
+        impl FieldSource<String> for NumSource {
+            fn resolve(&self, _: &String) -> Cow<'_, str> {
+                "123".into()
+            }
+        }
+        let path = from_template("/data/12:00/{num:05}-{num:02}-{num}", &NumSource);
+        assert_eq!(path.to_str().unwrap(), "/data/12:00/00123-123-123");
+    }
+
+    /// Truncation counts characters rather than bytes so multi-byte characters are never split
+    #[rstest::rstest]
+    #[case::ascii("cm12345-3", "{visit!trunc:4}", "cm12")]
+    #[case::shorter("cm1", "{visit!trunc:4}", "cm1")]
+    #[case::exact("cm12", "{visit!trunc:4}", "cm12")]
+    #[case::two_byte("éèêëē", "{visit!trunc:3}", "éèê")]
+    #[case::mixed_widths("aé€😀b", "{visit!trunc:4}", "aé€😀")]
+    #[case::emoji("😀😁😂", "{visit!trunc:1}", "😀")]
+    #[case::padded("é", "{visit!trunc:3:03}", "00é")]
+    #[case::truncated_then_padded("12345", "{visit!trunc:2:04}", "0012")]
+    #[case::optional("ñandú", "{?visit!trunc:2}", "ña")]
+    fn truncated_fields(
+        #[case] visit: &str,
+        #[case] template: &'static str,
+        #[case] expected: &str,
+    ) {
+        struct VisitSource<'v>(&'v str);
+        impl FieldSource<String> for VisitSource<'_> {
+            fn resolve(&self, _: &String) -> Cow<'_, str> {
+                self.0.into()
+            }
+        }
+        let path = from_template(template, &VisitSource(visit));
+        assert_eq!(path.to_str().unwrap(), expected);
+    }
+
+    #[rstest::rstest]
+    #[case::present("ROI", "12345/sub_ROI/DETECTOR")]
+    #[case::absent("", "12345/DETECTOR")]
+    fn optional_segments(#[case] roi: &str, #[case] expected: &str) {
+        struct RoiSource<'r>(&'r str);
+        impl FieldSource<String> for RoiSource<'_> {
+            fn resolve(&self, field: &String) -> Cow<'_, str> {
+                match field.as_str() {
+                    "roi" => self.0.into(),
+                    "scan_number" => "12345".into(),
+                    other => other.to_uppercase().into(),
+                }
+            }
+        }
+        let path = from_template("{scan_number}/sub_{?roi}/{detector}", &RoiSource(roi));
+        assert_eq!(path.to_str().unwrap(), expected);
+    }
+
+    #[test]
+    fn optional_fields_are_not_required() {
+        let template = PathTemplate::<String>::new("{scan_number}/{?roi}_{detector}").unwrap();
+        assert_eq!(
+            template.referenced_fields().collect::<Vec<_>>(),
+            ["scan_number", "roi", "detector"]
+        );
+        assert_eq!(
+            template.required_fields().collect::<Vec<_>>(),
+            ["scan_number", "detector"]
+        );
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for template in [
+            "/absolute/{path}",
+            "relative/{path}",
+            "{only}",
+            "12:00/{num:05}",
+            "{scan}/{?roi}/{?num:03}",
+            "{visit!trunc:8}/{?num!trunc:6:04}",
+        ] {
+            let pt = PathTemplate::<String>::new(template).unwrap();
+            assert_eq!(pt.to_string(), template);
+        }
     }
 
     #[rstest::rstest]
@@ -527,3 +1151,94 @@ mod path_template_tests {
         };
     }
 }
+
+#[cfg(test)]
+mod matcher_tests {
+    use super::*;
+
+    /// Only subdirectories can span multiple path segments and numbers must be numeric
+    fn accept(field: &str, value: &str) -> bool {
+        match field {
+            "sub" => true,
+            "num" => value.parse::<u32>().is_ok(),
+            _ => !value.contains('/'),
+        }
+    }
+
+    fn matches(template: &str, path: &str) -> Vec<Vec<(String, String)>> {
+        let template = PathTemplate::<String>::new(template).unwrap();
+        template
+            .matches(path, |f, v| accept(f, v), 10)
+            .into_iter()
+            .map(|m| m.into_iter().map(|(f, v)| (f.clone(), v)).collect())
+            .collect()
+    }
+
+    fn values(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(f, v)| (f.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[rstest::rstest]
+    #[case::literal("/tmp/data", "/tmp/data", &[])]
+    #[case::fields("/tmp/{inst}/{visit}", "/tmp/i22/cm1234-5", &[("inst", "i22"), ("visit", "cm1234-5")])]
+    #[case::literal_separated("{inst}-{num}", "i22-123", &[("inst", "i22"), ("num", "123")])]
+    #[case::repeated("/{inst}/{inst}-{num}", "/i22/i22-123", &[("inst", "i22"), ("num", "123")])]
+    #[case::padded("{num:05}", "00123", &[("num", "123")])]
+    #[case::too_wide_to_pad("{num:02}", "0123", &[("num", "0123")])]
+    #[case::truncated("{visit!trunc:4}", "cm12", &[("visit", "cm12")])]
+    #[case::optional_present("{?sub}/{num}", "foo/12", &[("sub", "foo"), ("num", "12")])]
+    #[case::optional_missing("{?sub}/{num}", "12", &[("sub", ""), ("num", "12")])]
+    #[case::empty_segment("{sub}/{num}", "12", &[("sub", ""), ("num", "12")])]
+    #[case::nested_subdirectory("{sub}/{num}", "a/b/12", &[("sub", "a/b"), ("num", "12")])]
+    fn unique_match(#[case] template: &str, #[case] path: &str, #[case] expected: &[(&str, &str)]) {
+        assert_eq!(matches(template, path), [values(expected)]);
+    }
+
+    #[rstest::rstest]
+    #[case::wrong_literal("/tmp/{inst}", "/data/i22")]
+    #[case::relative_path("/tmp/{inst}", "tmp/i22")]
+    #[case::absolute_path("{inst}/{num}", "/i22/12")]
+    #[case::inconsistent("{inst}/{inst}-{num}", "i22/b21-12")]
+    #[case::rejected_value("{inst}-{num}", "i22-abc")]
+    #[case::short_padding("{num:05}", "123")]
+    #[case::too_long_for_truncation("{visit!trunc:3}", "cm12")]
+    #[case::trailing("{inst}", "i22/extra/")]
+    fn no_match(#[case] template: &str, #[case] path: &str) {
+        assert_eq!(matches(template, path), Vec::<Vec<_>>::new());
+    }
+
+    #[test]
+    fn ambiguous() {
+        let found = matches("{inst}{sep}{num}", "ab12");
+        assert!(found.contains(&values(&[("inst", "a"), ("sep", "b"), ("num", "12")])));
+        assert!(found.contains(&values(&[("inst", "ab"), ("sep", "1"), ("num", "2")])));
+        assert!(found.contains(&values(&[("inst", ""), ("sep", "ab"), ("num", "12")])));
+        // Every candidate renders back to the same path
+        assert!(found
+            .iter()
+            .all(|m| { m.iter().map(|(_, v)| v.as_str()).collect::<String>() == "ab12" }));
+    }
+
+    #[test]
+    fn limit() {
+        let template = PathTemplate::<String>::new("{a}{b}").unwrap();
+        assert_eq!(template.matches("abcdef", |_, _| true, 3).len(), 3);
+    }
+
+    #[rstest::rstest]
+    #[case::adjacent_fields("{a}{b}{a}{b}{c}x", "a".repeat(4000))]
+    #[case::optional_segments(&"{?a}/".repeat(100), "b/".repeat(100))]
+    fn pathological(#[case] template: &str, #[case] path: String) {
+        let template = PathTemplate::<String>::new(template).unwrap();
+        let start = std::time::Instant::now();
+        template.matches(&path, |_, _| true, 16);
+        assert!(
+            start.elapsed() < std::time::Duration::from_secs(2),
+            "Matching took {:?}",
+            start.elapsed()
+        );
+    }
+}